@@ -0,0 +1,94 @@
+use std::time::Instant;
+
+use crate::{
+    servers::means_to_end::index::TimeIndex,
+    util::{Result, XorShift},
+};
+
+use super::parse_flags;
+
+const USAGE: &str = "usage:
+    bench-means-to-end [--inserts N] [--query-every N]";
+
+/// Replays one session of random inserts and range queries against a linear
+/// scan, as `Account` used to do, and against the time index.
+pub async fn run(args: &[String]) -> Result<()> {
+    let mut inserts: usize = 100_000;
+    let mut query_every: usize = 10;
+
+    for (flag, value) in parse_flags(args, &[])? {
+        match (flag, value) {
+            ("--inserts", Some(v)) => inserts = v.parse()?,
+            ("--query-every", Some(v)) => query_every = v.parse::<usize>()?.max(1),
+            _ => return Err(format!("Unknown flag {}\n{}", flag, USAGE).into()),
+        }
+    }
+
+    let mut rng = XorShift(0x2545_F491_4F6C_DD1D);
+    let operations: Vec<Operation> = (0..inserts)
+        .flat_map(|i| {
+            let insert = Operation::Insert(rng.next() as i32, (rng.next() % 10_000) as i32);
+            let query = (i % query_every == 0).then(|| {
+                let (a, b) = (rng.next() as i32, rng.next() as i32);
+                Operation::Query(a.min(b), a.max(b))
+            });
+            std::iter::once(insert).chain(query)
+        })
+        .collect();
+    let queries = operations.len() - inserts;
+
+    let start = Instant::now();
+    let mut deposits = vec![];
+    let mut linear = 0i64;
+    for operation in &operations {
+        match *operation {
+            Operation::Insert(timestamp, price) => deposits.push((timestamp, price)),
+            Operation::Query(min_time, max_time) => {
+                let prices: Vec<i64> = deposits
+                    .iter()
+                    .filter(|(t, _)| *t >= min_time && *t <= max_time)
+                    .map(|(_, p)| *p as i64)
+                    .collect();
+                if !prices.is_empty() {
+                    linear += prices.iter().sum::<i64>() / prices.len() as i64;
+                }
+            }
+        }
+    }
+    let linear_elapsed = start.elapsed();
+
+    let start = Instant::now();
+    let mut index = TimeIndex::new();
+    let mut indexed = 0i64;
+    for operation in &operations {
+        match *operation {
+            Operation::Insert(timestamp, price) => index.insert(timestamp, price),
+            Operation::Query(min_time, max_time) => {
+                let summary = index.summary(min_time, max_time);
+                if summary.count > 0 {
+                    indexed += summary.sum / summary.count as i64;
+                }
+            }
+        }
+    }
+    let indexed_elapsed = start.elapsed();
+
+    if linear != indexed {
+        return Err(format!("Results differ: {} != {}", linear, indexed).into());
+    }
+
+    println!("{} inserts, {} queries", index.len(), queries);
+    println!("linear scan  {:>10.1?}", linear_elapsed);
+    println!(
+        "time index   {:>10.1?}  ({:.1}x faster)",
+        indexed_elapsed,
+        linear_elapsed.as_secs_f64() / indexed_elapsed.as_secs_f64()
+    );
+
+    Ok(())
+}
+
+enum Operation {
+    Insert(i32, i32),
+    Query(i32, i32),
+}
@@ -0,0 +1,130 @@
+use std::time::Instant;
+
+use crate::{
+    servers::primetime::primality::Primality,
+    util::{Result, XorShift},
+};
+
+use super::parse_flags;
+
+const USAGE: &str = "usage:
+    bench-primetime [--requests N] [--distinct N] [--threads N] [--sieve-limit N] [--cache-bytes N]";
+
+struct Workload {
+    requests: usize,
+    distinct: usize,
+    threads: usize,
+    sieve_limit: u64,
+    cache_bytes: usize,
+}
+
+/// Times `isPrime` lookups over the same skewed workload with the sieve and
+/// cache disabled and enabled, so the speedup is visible before deploying.
+pub async fn run(args: &[String]) -> Result<()> {
+    let mut workload = Workload {
+        requests: 200_000,
+        distinct: 20_000,
+        threads: 4,
+        sieve_limit: 10_000_000,
+        cache_bytes: 16 * 1024 * 1024,
+    };
+
+    for (flag, value) in parse_flags(args, &[])? {
+        match (flag, value) {
+            ("--requests", Some(v)) => workload.requests = v.parse()?,
+            ("--distinct", Some(v)) => workload.distinct = v.parse::<usize>()?.max(1),
+            ("--threads", Some(v)) => workload.threads = v.parse::<usize>()?.max(1),
+            ("--sieve-limit", Some(v)) => workload.sieve_limit = v.parse()?,
+            ("--cache-bytes", Some(v)) => workload.cache_bytes = v.parse()?,
+            _ => return Err(format!("Unknown flag {}\n{}", flag, USAGE).into()),
+        }
+    }
+
+    let tokens = tokens(&workload);
+    let configurations = [
+        ("baseline", None, None),
+        ("sieve", Some(workload.sieve_limit), None),
+        ("cache", None, Some(workload.cache_bytes)),
+        (
+            "sieve + cache",
+            Some(workload.sieve_limit),
+            Some(workload.cache_bytes),
+        ),
+    ];
+
+    let mut baseline = None;
+    for (name, sieve_limit, cache_bytes) in configurations {
+        let start = Instant::now();
+        let primality = Primality::new(sieve_limit, cache_bytes);
+        let setup = start.elapsed();
+
+        let start = Instant::now();
+        let primes = lookup_all(&primality, &tokens, workload.threads);
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let throughput = tokens.len() as f64 / elapsed;
+        let speedup = throughput / *baseline.get_or_insert(throughput);
+
+        println!(
+            "{:<14} {:>12.0} req/s  {:>6.2}x  setup {:>8.1?}  ({} primes)",
+            name, throughput, speedup, setup, primes
+        );
+        if primality.is_enabled() {
+            println!("{:<14} {}", "", primality.metrics());
+        }
+    }
+
+    Ok(())
+}
+
+fn lookup_all(primality: &Primality, tokens: &[String], threads: usize) -> usize {
+    let chunk_size = tokens.len().div_ceil(threads).max(1);
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = tokens
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .filter(|token| primality.is_prime_token(token))
+                        .count()
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|h| h.join().unwrap_or(0)).sum()
+    })
+}
+
+/// A deterministic mix of small numbers and 30-40 digit ones, drawn so that
+/// a few distinct numbers are requested far more often than the rest.
+fn tokens(workload: &Workload) -> Vec<String> {
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+
+    let distinct: Vec<String> = (0..workload.distinct)
+        .map(|i| {
+            if i % 2 == 0 {
+                (rng.next() % workload.sieve_limit.max(2)).to_string()
+            } else {
+                let digits = 30 + rng.next() % 11;
+                let mut token = (1 + rng.next() % 9).to_string();
+                for _ in 1..digits {
+                    token.push(char::from(b'0' + (rng.next() % 10) as u8));
+                }
+                // skip the even numbers that are cheap either way
+                token.pop();
+                token.push('1');
+                token
+            }
+        })
+        .collect();
+
+    (0..workload.requests)
+        .map(|_| {
+            // squaring a uniform draw skews it toward the start of the list
+            let u = (rng.next() % 1_000_000) as f64 / 1_000_000.0;
+            distinct[(u * u * distinct.len() as f64) as usize].clone()
+        })
+        .collect()
+}
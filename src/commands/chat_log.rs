@@ -0,0 +1,347 @@
+use std::{
+    collections::VecDeque,
+    fs::Metadata,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{
+    fs::{self, File},
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+};
+
+use crate::{
+    servers::budget_chat::chat_log::{log_files, Entry, Event},
+    util::{env_var, Result},
+};
+
+use super::parse_flags;
+
+const USAGE: &str = "usage:
+    chat-log search [--path FILE] [--user NAME] [--since MILLIS] [--until MILLIS]
+    chat-log tail [--path FILE] [--user NAME] [-n LINES] [--follow]";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Default)]
+struct Filter {
+    user: Option<String>,
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+impl Filter {
+    fn matches(&self, entry: &Entry) -> bool {
+        self.user.as_ref().is_none_or(|u| *u == entry.username)
+            && self.since.is_none_or(|t| entry.timestamp >= t)
+            && self.until.is_none_or(|t| entry.timestamp <= t)
+    }
+}
+
+pub async fn run(args: &[String]) -> Result<()> {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => return Err(USAGE.into()),
+    };
+
+    let mut path = env_var::<PathBuf>("BUDGETCHAT_LOG_PATH")
+        .unwrap_or_else(|| PathBuf::from("budget_chat.log"));
+    let mut filter = Filter::default();
+    let mut lines = 10;
+    let mut follow = false;
+
+    for (flag, value) in parse_flags(args, &["--follow"])? {
+        match (flag, value) {
+            ("--path", Some(v)) => path = PathBuf::from(v),
+            ("--user", Some(v)) => filter.user = Some(v.to_string()),
+            ("--since", Some(v)) => filter.since = Some(v.parse()?),
+            ("--until", Some(v)) => filter.until = Some(v.parse()?),
+            ("-n", Some(v)) => lines = v.parse()?,
+            ("--follow", None) => follow = true,
+            _ => return Err(format!("Unknown flag {}\n{}", flag, USAGE).into()),
+        }
+    }
+
+    match command {
+        "search" => search(&path, &filter).await,
+        "tail" => tail(&path, &filter, lines, follow).await,
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn search(path: &Path, filter: &Filter) -> Result<()> {
+    for entry in matching_entries(path, filter).await? {
+        println!("{}", format_entry(&entry));
+    }
+
+    Ok(())
+}
+
+async fn tail(path: &Path, filter: &Filter, lines: usize, follow: bool) -> Result<()> {
+    for entry in last_entries(path, filter, lines).await? {
+        println!("{}", format_entry(&entry));
+    }
+
+    if follow {
+        follow_file(path, filter).await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the entries across every log file that match, oldest first.
+async fn matching_entries(path: &Path, filter: &Filter) -> Result<Vec<Entry>> {
+    let mut matching = vec![];
+
+    for file in log_files(path) {
+        for entry in read_entries(&file).await? {
+            if filter.matches(&entry) {
+                matching.push(entry);
+            }
+        }
+    }
+
+    Ok(matching)
+}
+
+/// Returns the last `lines` entries that match, oldest first.
+async fn last_entries(path: &Path, filter: &Filter, lines: usize) -> Result<Vec<Entry>> {
+    if lines == 0 {
+        return Ok(vec![]);
+    }
+
+    let mut last = VecDeque::with_capacity(lines);
+
+    for entry in matching_entries(path, filter).await? {
+        if last.len() == lines {
+            last.pop_front();
+        }
+        last.push_back(entry);
+    }
+
+    Ok(last.into())
+}
+
+/// Polls the active log file for new entries. Once it has been rotated,
+/// whatever is left in the old file is printed before the new one is
+/// followed from the beginning.
+async fn follow_file(path: &Path, filter: &Filter) -> Result<()> {
+    let mut followed = Followed::open(path, SeekFrom::End(0)).await;
+
+    loop {
+        if let Some(followed) = &mut followed {
+            print_entries(followed.read_new().await?, filter);
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let rotated = match &followed {
+            Some(followed) => followed.rotated(path).await,
+            None => fs::metadata(path).await.is_ok(),
+        };
+        if rotated {
+            if let Some(followed) = &mut followed {
+                print_entries(followed.read_new().await?, filter);
+            }
+            followed = Followed::open(path, SeekFrom::Start(0)).await;
+        }
+    }
+}
+
+fn print_entries(entries: Vec<Entry>, filter: &Filter) {
+    for entry in entries.iter().filter(|entry| filter.matches(entry)) {
+        println!("{}", format_entry(entry));
+    }
+}
+
+/// A log file being followed, which stays open after it's rotated.
+struct Followed {
+    reader: BufReader<File>,
+    id: Option<u64>,
+    /// A line that hasn't been completely written yet.
+    partial: String,
+}
+
+impl Followed {
+    async fn open(path: &Path, from: SeekFrom) -> Option<Followed> {
+        let mut file = File::open(path).await.ok()?;
+        let id = file_id(&file.metadata().await.ok()?);
+        file.seek(from).await.ok()?;
+
+        Some(Followed {
+            reader: BufReader::new(file),
+            id,
+            partial: String::new(),
+        })
+    }
+
+    /// Reads the entries written since the last call, skipping malformed
+    /// ones.
+    async fn read_new(&mut self) -> Result<Vec<Entry>> {
+        let mut entries = vec![];
+
+        loop {
+            let n = self.reader.read_line(&mut self.partial).await?;
+            // only consume complete lines so a partial write is picked up later
+            if n == 0 || !self.partial.ends_with('\n') {
+                return Ok(entries);
+            }
+
+            if let Ok(entry) = serde_json::from_str(&self.partial) {
+                entries.push(entry);
+            }
+            self.partial.clear();
+        }
+    }
+
+    /// Whether `path` is no longer the file being followed.
+    async fn rotated(&self, path: &Path) -> bool {
+        match fs::metadata(path).await {
+            Ok(metadata) => file_id(&metadata) != self.id,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Identifies a file across renames. Rotation isn't noticed on platforms
+/// without inode numbers.
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+async fn read_entries(path: &Path) -> Result<Vec<Entry>> {
+    let mut reader = BufReader::new(File::open(path).await?).lines();
+    let mut entries = vec![];
+
+    while let Some(line) = reader.next_line().await? {
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => eprintln!("Skipping malformed line in {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(entries)
+}
+
+fn format_entry(entry: &Entry) -> String {
+    let action = match entry.event {
+        Event::Join => "has entered the room".to_string(),
+        Event::Leave => "has left the room".to_string(),
+        Event::Message => format!("says: {}", entry.message.as_deref().unwrap_or("")),
+    };
+
+    format!(
+        "{} #{} {} ({}) {}",
+        entry.timestamp, entry.room, entry.username, entry.peer, action
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::servers::budget_chat::chat_log::{ChatLog, ChatLogConfig};
+
+    use super::*;
+
+    fn entry(timestamp: u64, username: &str) -> Entry {
+        let peer: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let mut entry = Entry::new(Event::Message, "main", username, peer).with_message("hi");
+        entry.timestamp = timestamp;
+        entry
+    }
+
+    #[tokio::test]
+    async fn test_search_and_tail() {
+        let dir = std::env::temp_dir().join(format!("chat-log-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("chat.log");
+
+        // small enough that the entries are spread over rotated files
+        let mut config = ChatLogConfig::new(&path);
+        config.max_bytes = 200;
+        let mut log = ChatLog::open(config).await.unwrap();
+        for (timestamp, username) in [
+            (1, "alice"),
+            (2, "bob"),
+            (3, "alice"),
+            (4, "carol"),
+            (5, "alice"),
+        ] {
+            log.append(&entry(timestamp, username)).await.unwrap();
+        }
+        assert!(log_files(&path).len() > 1);
+
+        let timestamps = |entries: Vec<Entry>| -> Vec<u64> {
+            entries.iter().map(|entry| entry.timestamp).collect()
+        };
+
+        let all = Filter::default();
+        assert_eq!(
+            timestamps(matching_entries(&path, &all).await.unwrap()),
+            [1, 2, 3, 4, 5]
+        );
+
+        let alice = Filter {
+            user: Some("alice".to_string()),
+            ..Filter::default()
+        };
+        assert_eq!(
+            timestamps(matching_entries(&path, &alice).await.unwrap()),
+            [1, 3, 5]
+        );
+
+        let window = Filter {
+            since: Some(2),
+            until: Some(4),
+            ..Filter::default()
+        };
+        assert_eq!(
+            timestamps(matching_entries(&path, &window).await.unwrap()),
+            [2, 3, 4]
+        );
+
+        assert_eq!(
+            timestamps(last_entries(&path, &alice, 2).await.unwrap()),
+            [3, 5]
+        );
+        assert_eq!(
+            timestamps(last_entries(&path, &all, 10).await.unwrap()),
+            [1, 2, 3, 4, 5]
+        );
+        assert!(last_entries(&path, &all, 0).await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_follow_across_rotation() {
+        let dir = std::env::temp_dir().join(format!("chat-log-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("chat.log");
+
+        // every append rotates the file
+        let mut config = ChatLogConfig::new(&path);
+        config.max_bytes = 1;
+        let mut log = ChatLog::open(config).await.unwrap();
+        let mut followed = Followed::open(&path, SeekFrom::End(0)).await.unwrap();
+
+        log.append(&entry(1, "alice")).await.unwrap();
+        assert!(followed.rotated(&path).await);
+        assert_eq!(followed.read_new().await.unwrap(), [entry(1, "alice")]);
+
+        let mut followed = Followed::open(&path, SeekFrom::Start(0)).await.unwrap();
+        assert!(!followed.rotated(&path).await);
+        assert!(followed.read_new().await.unwrap().is_empty());
+        log.append(&entry(2, "bob")).await.unwrap();
+        assert_eq!(followed.read_new().await.unwrap(), [entry(2, "bob")]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+use crate::util::Result;
+
+pub mod bench_means_to_end;
+pub mod bench_primetime;
+pub mod chat_log;
+pub mod replay_means_to_end;
+
+pub async fn run(args: &[String]) -> Result<()> {
+    match args.first().map(|s| s.as_str()) {
+        Some("bench-means-to-end") => bench_means_to_end::run(&args[1..]).await,
+        Some("bench-primetime") => bench_primetime::run(&args[1..]).await,
+        Some("chat-log") => chat_log::run(&args[1..]).await,
+        Some("replay-means-to-end") => replay_means_to_end::run(&args[1..]).await,
+        Some(command) => Err(format!("Unknown command {:?}", command).into()),
+        None => Err("No command given".into()),
+    }
+}
+
+/// Splits `--flag value` style arguments into (flag, value) pairs. Flags
+/// listed in `switches` take no value.
+pub fn parse_flags<'a>(
+    args: &'a [String],
+    switches: &[&str],
+) -> Result<Vec<(&'a str, Option<&'a str>)>> {
+    let mut flags = vec![];
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with('-') {
+            return Err(format!("Unexpected argument {:?}", arg).into());
+        }
+
+        if switches.contains(&arg.as_str()) {
+            flags.push((arg.as_str(), None));
+        } else {
+            match iter.next() {
+                Some(value) => flags.push((arg.as_str(), Some(value.as_str()))),
+                None => return Err(format!("Missing value for {}", arg).into()),
+            }
+        }
+    }
+
+    Ok(flags)
+}
@@ -0,0 +1,167 @@
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
+    net::TcpStream,
+};
+
+use crate::{
+    servers::means_to_end::{dump, Deposit},
+    util::Result,
+};
+
+use super::parse_flags;
+
+const USAGE: &str = "usage:
+    replay-means-to-end --file PATH [--addr HOST:PORT] [--series NAME] [--query [OPCODE:]MIN:MAX]...";
+
+/// Replays a session dump against a running server as inserts, then sends
+/// each query and prints its answer. Without `--query` the mean over every
+/// timestamp is asked for.
+pub async fn run(args: &[String]) -> Result<()> {
+    let mut file = None;
+    let mut addr = "127.0.0.1:3010".to_string();
+    let mut series = None;
+    let mut queries = vec![];
+
+    for (flag, value) in parse_flags(args, &[])? {
+        match (flag, value) {
+            ("--file", Some(v)) => file = Some(v.to_string()),
+            ("--addr", Some(v)) => addr = v.to_string(),
+            ("--series", Some(v)) => series = Some(v.to_string()),
+            ("--query", Some(v)) => queries.push(parse_query(v)?),
+            _ => return Err(format!("Unknown flag {}\n{}", flag, USAGE).into()),
+        }
+    }
+
+    let file = file.ok_or_else(|| format!("Missing --file\n{}", USAGE))?;
+    if queries.is_empty() {
+        queries.push((b'Q', i32::MIN, i32::MAX));
+    }
+
+    let deposits = dump::decode(&fs::read_to_string(&file).await?)?;
+    let answers = replay(&addr, series.as_deref(), &deposits, &queries).await?;
+    println!("Inserted {} deposits from {}", deposits.len(), file);
+
+    for ((opcode, min_time, max_time), answer) in queries.into_iter().zip(answers) {
+        println!("{} {} {} => {}", opcode as char, min_time, max_time, answer);
+    }
+
+    Ok(())
+}
+
+/// Sends the deposits as inserts, then each query, returning the answers.
+async fn replay(
+    addr: &str,
+    series: Option<&str>,
+    deposits: &[Deposit],
+    queries: &[(u8, i32, i32)],
+) -> Result<Vec<i64>> {
+    let mut socket = TcpStream::connect(addr).await?;
+    let (mut reader, writer) = socket.split();
+    let mut writer = BufWriter::new(writer);
+
+    if let Some(name) = series {
+        let length = u8::try_from(name.len())
+            .map_err(|_| format!("Series name of {} bytes is too long", name.len()))?;
+        writer.write_all(&[b'N', length]).await?;
+        writer.write_all(name.as_bytes()).await?;
+    }
+    for deposit in deposits {
+        writer
+            .write_all(&message(b'I', deposit.timestamp, deposit.price))
+            .await?;
+    }
+    writer.flush().await?;
+
+    let mut answers = vec![];
+    for &(opcode, min_time, max_time) in queries {
+        writer
+            .write_all(&message(opcode, min_time, max_time))
+            .await?;
+        writer.flush().await?;
+
+        answers.push(if opcode == b'S' {
+            reader.read_i64().await?
+        } else {
+            reader.read_i32().await? as i64
+        });
+    }
+
+    Ok(answers)
+}
+
+fn parse_query(query: &str) -> Result<(u8, i32, i32)> {
+    let parts: Vec<&str> = query.split(':').collect();
+    let (opcode, min_time, max_time) = match parts[..] {
+        [min_time, max_time] => ("Q", min_time, max_time),
+        [opcode, min_time, max_time] => (opcode, min_time, max_time),
+        _ => return Err(format!("Invalid query {:?}\n{}", query, USAGE).into()),
+    };
+
+    match opcode.as_bytes() {
+        [opcode] => Ok((*opcode, min_time.parse()?, max_time.parse()?)),
+        _ => Err(format!("Invalid opcode {:?}", opcode).into()),
+    }
+}
+
+fn message(opcode: u8, a: i32, b: i32) -> [u8; 9] {
+    let mut raw_message = [opcode; 9];
+    raw_message[1..5].copy_from_slice(&a.to_be_bytes());
+    raw_message[5..9].copy_from_slice(&b.to_be_bytes());
+    raw_message
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::net::TcpListener;
+
+    use crate::servers::means_to_end::{self, dump::Format, policy::Policies, Config, Mode};
+
+    use super::*;
+
+    async fn start_server(mode: Mode, store_path: Option<PathBuf>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = Config {
+            mode,
+            store_path,
+            policies: Policies::default(),
+            dump_path: None,
+            dump_format: Format::Csv,
+        };
+
+        tokio::spawn(async move {
+            let served = means_to_end::accept_connections(listener, config).await;
+            served.map_err(|e| e.to_string())
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_replay_against_server() {
+        let dump = "timestamp,price\n12345,101\n12346,102\n12347,100\n40960,5\n";
+        let deposits = dump::decode(dump).unwrap();
+
+        let addr = start_server(Mode::Extended, None).await;
+        let queries = [(b'Q', 12288, 16384), (b'M', 0, 50000), (b'S', 0, 50000)];
+        let answers = replay(&addr, None, &deposits, &queries).await.unwrap();
+        assert_eq!(answers, [101, 100, 308]);
+
+        // a named series keeps the deposits for the next connection
+        let dir = std::env::temp_dir().join(format!("replay-{}", uuid::Uuid::new_v4()));
+        let addr = start_server(Mode::Strict, Some(dir.clone())).await;
+        // answering the query means every insert before it was stored
+        let mean = [(b'Q', 0, 50000)];
+        let answers = replay(&addr, Some("btc"), &deposits, &mean).await;
+        assert_eq!(answers.unwrap(), [77]);
+        let answers = replay(&addr, Some("btc"), &[], &mean).await;
+        assert_eq!(answers.unwrap(), [77]);
+
+        let name = "x".repeat(256);
+        assert!(replay(&addr, Some(&name), &deposits, &[]).await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,350 @@
+use std::future::Future;
+
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+
+use crate::util::Result;
+
+/// Largest request head or body accepted.
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// `HTTP/1.0` or `HTTP/1.1`.
+    pub version: String,
+    /// The path with `%XX` escapes decoded, lossily if they aren't UTF-8.
+    pub path: String,
+    /// The path as sent, for paths that name raw bytes.
+    pub raw_path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// HTTP/1.1 connections stay open unless the client sends
+    /// `Connection: close`, HTTP/1.0 ones only if it asks for keep-alive.
+    fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection");
+        if self.version == "HTTP/1.0" {
+            connection.is_some_and(|c| c.eq_ignore_ascii_case("keep-alive"))
+        } else {
+            !connection.is_some_and(|c| c.eq_ignore_ascii_case("close"))
+        }
+    }
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status,
+            content_type,
+            body: body.into(),
+            headers: vec![],
+        }
+    }
+
+    pub fn json(status: u16, body: &serde_json::Value) -> Response {
+        let mut body = body.to_string().into_bytes();
+        body.push(b'\n');
+        Response::new(status, "application/json", body)
+    }
+
+    pub fn text(status: u16, body: &str) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", format!("{}\n", body))
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Response {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+/// Serves requests on one connection until the client closes it or asks
+/// not to keep it alive. This is just enough HTTP/1.1 for small JSON APIs:
+/// `Content-Length` bodies and keep-alive, but no chunked requests or TLS.
+pub async fn serve_connection<S, H, F>(socket: S, handler: H) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    H: Fn(Request) -> F,
+    F: Future<Output = Response>,
+{
+    let (read_half, mut writer) = tokio::io::split(socket);
+    let mut reader = BufReader::new(read_half);
+
+    loop {
+        // the error is boxed and not `Send`, so it can't be held across the
+        // write below
+        let request = match read_request(&mut reader).await.map_err(|e| e.to_string()) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                let response = Response::text(400, &e);
+                return write_response(&mut writer, &response, false).await;
+            }
+        };
+
+        let keep_alive = request.keep_alive();
+        let response = handler(request).await;
+        write_response(&mut writer, &response, keep_alive).await?;
+
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads one request, or `None` if the connection closed between requests.
+pub async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Request>> {
+    let mut request_line = String::new();
+    if read_head_line(reader, &mut request_line, MAX_REQUEST_BYTES).await? == 0 {
+        return Ok(None);
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_string(), target.to_string(), version.to_string())
+        }
+        _ => return Err(format!("Malformed request line {:?}", request_line.trim_end()).into()),
+    };
+
+    let mut headers = vec![];
+    let mut head_bytes = request_line.len();
+    loop {
+        let mut line = String::new();
+        let limit = MAX_REQUEST_BYTES.saturating_sub(head_bytes);
+        let n = read_head_line(reader, &mut line, limit).await?;
+        head_bytes += n;
+
+        if n == 0 || head_bytes > MAX_REQUEST_BYTES {
+            return Err("Incomplete request head".into());
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+
+        match line.split_once(':') {
+            Some((name, value)) => {
+                headers.push((name.trim().to_string(), value.trim().to_string()))
+            }
+            None => return Err(format!("Malformed header {:?}", line).into()),
+        }
+    }
+
+    let mut request = Request {
+        method,
+        version,
+        path: String::new(),
+        raw_path: String::new(),
+        query: vec![],
+        headers,
+        body: vec![],
+    };
+
+    if request.header("Transfer-Encoding").is_some() {
+        return Err("Chunked request bodies aren't supported".into());
+    }
+
+    let length: usize = match request.header("Content-Length") {
+        Some(length) => length.parse()?,
+        None => 0,
+    };
+    if length > MAX_REQUEST_BYTES {
+        return Err(format!("Request body of {} bytes is too large", length).into());
+    }
+
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body).await?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    request.path = percent_decode(path);
+    request.raw_path = path.to_string();
+    request.query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                percent_decode(&key.replace('+', " ")),
+                percent_decode(&value.replace('+', " ")),
+            )
+        })
+        .collect();
+
+    Ok(Some(request))
+}
+
+/// Reads one line of the request head, reading at most one byte past
+/// `limit` so an endless line can't grow the buffer unbounded.
+async fn read_head_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut String,
+    limit: usize,
+) -> Result<usize> {
+    let n = reader.take(limit as u64 + 1).read_line(line).await?;
+    if n > limit {
+        return Err("Request head is too large".into());
+    }
+
+    Ok(n)
+}
+
+pub async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: &Response,
+    keep_alive: bool,
+) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_type,
+        response.body.len(),
+        if keep_alive { "keep-alive" } else { "close" },
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(&response.body).await?;
+    writer.flush().await?;
+
+    Ok(())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Decodes `%XX` escapes into text, replacing bytes that aren't UTF-8.
+fn percent_decode(s: &str) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(s)).into_owned()
+}
+
+/// Decodes `%XX` escapes, leaving invalid ones as they are.
+pub fn percent_decode_bytes(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let raw = "POST /isPrime?n=1%2B2&flag&x=a+b HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody";
+        let mut reader = BufReader::new(raw.as_bytes());
+
+        let request = read_request(&mut reader).await.unwrap().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/isPrime");
+        assert_eq!(request.raw_path, "/isPrime");
+        assert_eq!(request.query_param("n"), Some("1+2"));
+        assert_eq!(request.query_param("flag"), Some(""));
+        assert_eq!(request.query_param("x"), Some("a b"));
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.body, b"body");
+
+        assert!(read_request(&mut reader).await.unwrap().is_none());
+
+        let raw = "GET /keys/%FF%3d%zz HTTP/1.1\r\n\r\n";
+        let request = read_request(&mut BufReader::new(raw.as_bytes()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.path, "/keys/\u{fffd}=%zz");
+        assert_eq!(percent_decode_bytes(&request.raw_path), b"/keys/\xff=%zz");
+
+        assert!(
+            read_request(&mut BufReader::new("nonsense\r\n\r\n".as_bytes()))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_and_head_limit() {
+        for (raw, keep_alive) in [
+            ("GET / HTTP/1.1\r\n\r\n", true),
+            ("GET / HTTP/1.1\r\nConnection: close\r\n\r\n", false),
+            ("GET / HTTP/1.0\r\n\r\n", false),
+            ("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n", true),
+        ] {
+            let mut reader = BufReader::new(raw.as_bytes());
+            let request = read_request(&mut reader).await.unwrap().unwrap();
+            assert_eq!(request.keep_alive(), keep_alive, "{:?}", raw);
+        }
+
+        let endless = vec![b'a'; MAX_REQUEST_BYTES * 2];
+        let mut reader = BufReader::new(&endless[..]);
+        assert!(read_request(&mut reader).await.is_err());
+
+        let mut long_header = b"GET / HTTP/1.1\r\nX: ".to_vec();
+        long_header.extend(vec![b'a'; MAX_REQUEST_BYTES]);
+        let mut reader = BufReader::new(&long_header[..]);
+        assert!(read_request(&mut reader).await.is_err());
+    }
+}
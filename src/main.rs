@@ -1,26 +1,41 @@
+mod commands;
+mod http;
 mod servers;
 mod util;
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = commands::run(&args).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let task1 = tokio::spawn(async {
         servers::smoketest::start("3000").await.unwrap();
     });
 
     let task2 = tokio::spawn(async {
-        servers::primetime::start("3005").await.unwrap();
+        let config = servers::primetime::Config::from_env();
+        servers::primetime::start("3005", config).await.unwrap();
     });
 
     let task3 = tokio::spawn(async {
-        servers::means_to_end::start("3010").await.unwrap();
+        let config = servers::means_to_end::Config::from_env();
+        servers::means_to_end::start("3010", config).await.unwrap();
     });
 
     let task4 = tokio::spawn(async {
-        servers::budget_chat::start("3015").await.unwrap();
+        let config = servers::budget_chat::Config::from_env();
+        servers::budget_chat::start("3015", config).await.unwrap();
     });
 
     let task5 = tokio::spawn(async {
-        servers::unusual_database_program::start("3020")
+        let config = servers::unusual_database_program::Config::from_env();
+        servers::unusual_database_program::start("3020", config)
             .await
             .unwrap();
     });
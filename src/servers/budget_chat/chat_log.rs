@@ -0,0 +1,203 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::util::Result;
+
+#[derive(Debug, Clone)]
+pub struct ChatLogConfig {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Event {
+    Join,
+    Leave,
+    Message,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Entry {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub event: Event,
+    pub room: String,
+    pub username: String,
+    pub peer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Append-only JSON lines log that rotates once the active file reaches
+/// `max_bytes`. Rotated files are named `<path>.1` (newest) up to
+/// `<path>.<max_files>` (oldest).
+pub struct ChatLog {
+    config: ChatLogConfig,
+    file: File,
+    size: u64,
+}
+
+impl ChatLogConfig {
+    pub fn new(path: impl Into<PathBuf>) -> ChatLogConfig {
+        ChatLogConfig {
+            path: path.into(),
+            max_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+impl Entry {
+    pub fn new(event: Event, room: &str, username: &str, peer: SocketAddr) -> Entry {
+        Entry {
+            timestamp: now_millis(),
+            event,
+            room: room.to_string(),
+            username: username.to_string(),
+            peer: peer.to_string(),
+            message: None,
+        }
+    }
+
+    pub fn with_message(mut self, message: &str) -> Entry {
+        self.message = Some(message.trim_end_matches(['\r', '\n']).to_string());
+        self
+    }
+}
+
+impl ChatLog {
+    pub async fn open(config: ChatLogConfig) -> Result<ChatLog> {
+        let file = open_append(&config.path).await?;
+        let size = file.metadata().await?.len();
+
+        Ok(ChatLog { config, file, size })
+    }
+
+    pub async fn append(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        self.file.write_all(&line).await?;
+        self.file.flush().await?;
+        self.size += line.len() as u64;
+
+        if self.size >= self.config.max_bytes {
+            self.rotate().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        let path = &self.config.path;
+
+        if self.config.max_files == 0 {
+            fs::remove_file(path).await?;
+        } else {
+            for n in (1..self.config.max_files).rev() {
+                let from = rotated_path(path, n);
+                if fs::metadata(&from).await.is_ok() {
+                    fs::rename(&from, rotated_path(path, n + 1)).await?;
+                }
+            }
+            fs::rename(path, rotated_path(path, 1)).await?;
+        }
+
+        self.file = open_append(path).await?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+/// Returns the log files that exist for `path`, oldest first.
+pub fn log_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+
+    let mut n = 1;
+    while rotated_path(path, n).exists() {
+        files.push(rotated_path(path, n));
+        n += 1;
+    }
+    files.reverse();
+
+    if path.exists() {
+        files.push(path.to_path_buf());
+    }
+
+    files
+}
+
+pub fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+async fn open_append(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent).await?;
+        }
+    }
+
+    Ok(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("chat-log-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("chat.log");
+        let peer: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+
+        let mut config = ChatLogConfig::new(&path);
+        config.max_bytes = 1;
+        config.max_files = 2;
+
+        let mut log = ChatLog::open(config).await.unwrap();
+        for name in ["alice", "bob", "carol"] {
+            log.append(&Entry::new(Event::Join, "main", name, peer))
+                .await
+                .unwrap();
+        }
+
+        // the oldest entry was rotated out and the active file is empty
+        let files = log_files(&path);
+        assert_eq!(
+            files,
+            vec![rotated_path(&path, 2), rotated_path(&path, 1), path]
+        );
+
+        let oldest = std::fs::read_to_string(&files[0]).unwrap();
+        let entry: Entry = serde_json::from_str(oldest.trim()).unwrap();
+        assert_eq!(entry.username, "bob");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,530 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, WriteHalf},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc,
+        oneshot::{self, error::TryRecvError},
+        RwLock,
+    },
+};
+
+use crate::util::Result;
+
+use super::{handle_connection, Server, PREFIX, USERNAME_RE};
+
+const SERVER_NAME: &str = "budgetchat";
+
+/// Size of the in-memory pipe between an IRC client and the chat server.
+const BRIDGE_BUFFER_SIZE: usize = 64 * 1024;
+
+const WELCOME_PROMPT: &str = "Welcome to budgetchat! What shall I call you?";
+
+pub async fn start(port: &str, server_lock: Arc<RwLock<Server>>) -> Result<()> {
+    let address = format!("0.0.0.0:{}", port);
+    let listener = TcpListener::bind(&address).await?;
+
+    println!("[{}] IRC listening on {}", PREFIX, &address);
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let server_lock_clone = server_lock.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_irc(socket, addr, server_lock_clone).await {
+                eprintln!("[{}] IRC error occurred: {}", PREFIX, e);
+            }
+        });
+    }
+}
+
+/// An IRC client connection. Joining the channel starts a regular chat
+/// session over an in-memory pipe; the client's PRIVMSGs are written to it as
+/// chat lines and the lines the server sends back are translated into IRC
+/// messages.
+struct Client {
+    addr: SocketAddr,
+    channel: String,
+    nick: Option<String>,
+    got_user: bool,
+    registered: bool,
+    session: Option<Session>,
+    replies: mpsc::UnboundedSender<String>,
+    server_lock: Arc<RwLock<Server>>,
+}
+
+/// The chat session a client starts by joining the channel.
+struct Session {
+    writer: WriteHalf<DuplexStream>,
+    /// Closed once the server has ended the session, by refusing the join or
+    /// removing the user.
+    ended: oneshot::Receiver<()>,
+}
+
+async fn handle_irc(
+    socket: TcpStream,
+    addr: SocketAddr,
+    server_lock: Arc<RwLock<Server>>,
+) -> Result<()> {
+    println!("[{}] IRC connection established from {}", PREFIX, addr);
+
+    let (read_half, mut write_half) = socket.into_split();
+    let (replies, mut outgoing) = mpsc::unbounded_channel::<String>();
+
+    let writer = tokio::spawn(async move {
+        while let Some(line) = outgoing.recv().await {
+            if write_half
+                .write_all(format!("{}\r\n", line).as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        let _ = write_half.shutdown().await;
+    });
+
+    let channel = format!("#{}", server_lock.read().await.room);
+    let mut client = Client {
+        addr,
+        channel,
+        nick: None,
+        got_user: false,
+        registered: false,
+        session: None,
+        replies,
+        server_lock,
+    };
+
+    let mut lines = BufReader::new(read_half).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let (command, params) = match parse_line(&line) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+
+        if !client.handle(&command, &params).await {
+            break;
+        }
+    }
+
+    client.part().await;
+    // the writer finishes once every reply sender, including the session
+    // translator's, has been dropped
+    drop(client);
+    writer.await?;
+
+    Ok(())
+}
+
+impl Client {
+    /// Handles one client command, returning false once the client quits.
+    async fn handle(&mut self, command: &str, params: &[String]) -> bool {
+        match command {
+            "CAP" => {
+                if params.first().map(|p| p.as_str()) == Some("LS") {
+                    self.send(format!(":{} CAP * LS :", SERVER_NAME));
+                }
+            }
+            "NICK" => self.set_nick(params.first()),
+            "USER" => {
+                self.got_user = true;
+                self.try_register();
+            }
+            "PING" => {
+                let token = params.first().map(|p| p.as_str()).unwrap_or(SERVER_NAME);
+                self.send(format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, token));
+            }
+            "PONG" => {}
+            _ if !self.registered => self.numeric("451", ":You have not registered"),
+            "JOIN" => self.join(params.first()).await,
+            "PART" => {
+                if self.check_channel(params.first()) {
+                    self.part().await;
+                }
+            }
+            "PRIVMSG" | "NOTICE" => self.privmsg(params).await,
+            "NAMES" => self.names().await,
+            "QUIT" => return false,
+            _ => self.numeric("421", &format!("{} :Unknown command", command)),
+        }
+
+        true
+    }
+
+    fn set_nick(&mut self, nick: Option<&String>) {
+        let nick = match nick {
+            Some(nick) => nick,
+            None => return self.numeric("431", ":No nickname given"),
+        };
+
+        if self.session().is_some() {
+            return self.numeric("484", ":Nick changes are not supported in the room");
+        }
+
+        if !USERNAME_RE.is_match(nick) {
+            return self.numeric("432", &format!("{} :Erroneous nickname", nick));
+        }
+
+        self.nick = Some(nick.to_string());
+        self.try_register();
+    }
+
+    fn try_register(&mut self) {
+        if self.registered || !self.got_user || self.nick.is_none() {
+            return;
+        }
+
+        self.registered = true;
+        self.numeric("001", &format!(":Welcome to budgetchat, {}", self.nick()));
+        self.numeric("422", ":MOTD File is missing");
+    }
+
+    async fn join(&mut self, channel: Option<&String>) {
+        if !self.check_channel(channel) || self.session().is_some() {
+            return;
+        }
+
+        let (chat_side, bridge_side) = tokio::io::duplex(BRIDGE_BUFFER_SIZE);
+        let (bridge_reader, mut bridge_writer) = tokio::io::split(bridge_side);
+
+        // answer the welcome prompt up front, the translator skips it
+        if bridge_writer
+            .write_all(format!("{}\n", self.nick()).as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+        let (end, ended) = oneshot::channel::<()>();
+        self.session = Some(Session {
+            writer: bridge_writer,
+            ended,
+        });
+
+        let addr = self.addr;
+        let server_lock = self.server_lock.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(chat_side, addr, server_lock).await {
+                eprintln!("[{}] Error occurred: {}", PREFIX, e);
+            }
+        });
+
+        let nick = self.nick().to_string();
+        let channel = self.channel.clone();
+        let replies = self.replies.clone();
+        tokio::spawn(async move {
+            // dropped when the server closes its end of the pipe
+            let _end = end;
+            let mut lines = BufReader::new(bridge_reader).lines();
+            let mut joined = false;
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                joined |= line.starts_with("* The room contains:");
+
+                for reply in translate(&line, &nick, &channel) {
+                    let _ = replies.send(reply);
+                }
+            }
+
+            if joined {
+                let _ = replies.send(format!(":{} PART {}", user_prefix(&nick), channel));
+            }
+        });
+    }
+
+    /// Ends the chat session, if any. The translator announces the PART once
+    /// the server has removed the user.
+    async fn part(&mut self) {
+        if let Some(mut session) = self.session.take() {
+            let _ = session.writer.shutdown().await;
+        }
+    }
+
+    /// Returns the writer for the chat session, forgetting the session once
+    /// the server has ended it so the client can join again.
+    fn session(&mut self) -> Option<&mut WriteHalf<DuplexStream>> {
+        let ended = self
+            .session
+            .as_mut()
+            .is_some_and(|session| session.ended.try_recv() != Err(TryRecvError::Empty));
+        if ended {
+            self.session = None;
+        }
+
+        self.session.as_mut().map(|session| &mut session.writer)
+    }
+
+    async fn privmsg(&mut self, params: &[String]) {
+        let (target, text) = match params {
+            [target, text, ..] => (target, text),
+            _ => return self.numeric("412", ":No text to send"),
+        };
+
+        if !target.eq_ignore_ascii_case(&self.channel) {
+            return self.numeric("401", &format!("{} :No such nick/channel", target));
+        }
+
+        let channel = self.channel.clone();
+        let session = match self.session() {
+            Some(session) => session,
+            None => {
+                let message = format!("{} :You're not on that channel", channel);
+                return self.numeric("442", &message);
+            }
+        };
+
+        let line = format!("{}\n", text.replace(['\r', '\n'], " "));
+        if session.write_all(line.as_bytes()).await.is_err() {
+            self.session = None;
+        }
+    }
+
+    async fn names(&self) {
+        let usernames = self.server_lock.read().await.get_usernames();
+        self.send_names(&usernames.replace(", ", " "));
+    }
+
+    fn send_names(&self, names: &str) {
+        self.numeric("353", &format!("= {} :{}", self.channel, names));
+        self.numeric("366", &format!("{} :End of /NAMES list", self.channel));
+    }
+
+    fn check_channel(&self, channel: Option<&String>) -> bool {
+        match channel {
+            Some(channel) if channel.eq_ignore_ascii_case(&self.channel) => true,
+            Some(channel) => {
+                self.numeric("403", &format!("{} :No such channel", channel));
+                false
+            }
+            None => {
+                self.numeric("461", ":Not enough parameters");
+                false
+            }
+        }
+    }
+
+    fn nick(&self) -> &str {
+        self.nick.as_deref().unwrap_or("*")
+    }
+
+    /// Sends a numeric reply; `params` must already mark its trailing
+    /// parameter with `:`.
+    fn numeric(&self, code: &str, params: &str) {
+        self.send(format!(
+            ":{} {} {} {}",
+            SERVER_NAME,
+            code,
+            self.nick(),
+            params
+        ));
+    }
+
+    fn send(&self, line: String) {
+        let _ = self.replies.send(line);
+    }
+}
+
+/// Splits an IRC line into its upper-cased command and parameters, dropping
+/// any prefix. A parameter starting with `:` swallows the rest of the line.
+fn parse_line(line: &str) -> Option<(String, Vec<String>)> {
+    let mut rest = line.trim_end_matches(['\r', '\n']).trim_start();
+
+    if rest.starts_with(':') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+
+    let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    if command.is_empty() {
+        return None;
+    }
+
+    let mut params = vec![];
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+
+        if let Some(trailing) = rest.strip_prefix(':') {
+            params.push(trailing.to_string());
+            break;
+        }
+
+        let (param, remaining) = rest.split_once(' ').unwrap_or((rest, ""));
+        params.push(param.to_string());
+        rest = remaining;
+    }
+
+    Some((command.to_ascii_uppercase(), params))
+}
+
+/// Translates a line sent by the chat server into IRC messages for `nick`.
+fn translate(line: &str, nick: &str, channel: &str) -> Vec<String> {
+    if line == WELCOME_PROMPT {
+        return vec![];
+    }
+
+    if let Some(names) = line.strip_prefix("* The room contains:") {
+        let mut names: Vec<&str> = names.split(',').map(|n| n.trim()).collect();
+        names.retain(|n| !n.is_empty());
+        names.push(nick);
+
+        return vec![
+            format!(":{} JOIN {}", user_prefix(nick), channel),
+            format!(
+                ":{} 353 {} = {} :{}",
+                SERVER_NAME,
+                nick,
+                channel,
+                names.join(" ")
+            ),
+            format!(
+                ":{} 366 {} {} :End of /NAMES list",
+                SERVER_NAME, nick, channel
+            ),
+        ];
+    }
+
+    if let Some(rest) = line.strip_prefix("* ") {
+        if let Some(name) = rest.strip_suffix(" has entered the room") {
+            return vec![format!(":{} JOIN {}", user_prefix(name), channel)];
+        }
+
+        if let Some(name) = rest.strip_suffix(" has left the room") {
+            return vec![format!(":{} PART {}", user_prefix(name), channel)];
+        }
+
+        return vec![format!(":{} NOTICE {} :{}", SERVER_NAME, nick, rest)];
+    }
+
+    if let Some((name, message)) = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once("] "))
+    {
+        return vec![format!(
+            ":{} PRIVMSG {} :{}",
+            user_prefix(name),
+            channel,
+            message
+        )];
+    }
+
+    vec![format!(":{} NOTICE {} :{}", SERVER_NAME, nick, line)]
+}
+
+fn user_prefix(nick: &str) -> String {
+    format!("{}!{}@{}", nick, nick, SERVER_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::moderation::{BanList, ModerationConfig, Target};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rejoin_after_refused_join() {
+        let mut bans = BanList::default();
+        bans.ban(Target::Name("alice".to_string())).await.unwrap();
+        let server = Server::new("main".to_string(), None, ModerationConfig::default(), bans);
+
+        let (replies, mut sent) = mpsc::unbounded_channel();
+        let mut client = Client {
+            addr: "127.0.0.1:1234".parse().unwrap(),
+            channel: "#main".to_string(),
+            nick: None,
+            got_user: false,
+            registered: false,
+            session: None,
+            replies,
+            server_lock: Arc::new(RwLock::new(server)),
+        };
+        let channel = Some("#main".to_string());
+
+        client.handle("NICK", &["alice".to_string()]).await;
+        client.handle("USER", &["alice".to_string()]).await;
+        client.join(channel.as_ref()).await;
+
+        // the refused session is forgotten once the server has closed it
+        for _ in 0..100 {
+            if client.session().is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(client.session().is_none());
+
+        client.handle("NICK", &["bob".to_string()]).await;
+        client.join(channel.as_ref()).await;
+
+        let mut lines = vec![];
+        while !lines.contains(&":bob!bob@budgetchat JOIN #main".to_string()) {
+            let line = tokio::time::timeout(Duration::from_secs(5), sent.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            lines.push(line);
+        }
+        assert!(lines.contains(&":budgetchat NOTICE alice :You are banned".to_string()));
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line("PRIVMSG #main :hello there\r\n"),
+            Some((
+                "PRIVMSG".to_string(),
+                vec!["#main".to_string(), "hello there".to_string()]
+            ))
+        );
+        assert_eq!(
+            parse_line(":alice!a@host join #main"),
+            Some(("JOIN".to_string(), vec!["#main".to_string()]))
+        );
+        assert_eq!(
+            parse_line("USER alice 0 * :Alice A"),
+            Some((
+                "USER".to_string(),
+                vec![
+                    "alice".to_string(),
+                    "0".to_string(),
+                    "*".to_string(),
+                    "Alice A".to_string()
+                ]
+            ))
+        );
+        assert_eq!(parse_line(""), None);
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(
+            translate(WELCOME_PROMPT, "bob", "#main"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            translate("* The room contains: alice, carol", "bob", "#main"),
+            vec![
+                ":bob!bob@budgetchat JOIN #main".to_string(),
+                ":budgetchat 353 bob = #main :alice carol bob".to_string(),
+                ":budgetchat 366 bob #main :End of /NAMES list".to_string(),
+            ]
+        );
+        assert_eq!(
+            translate("* alice has left the room", "bob", "#main"),
+            vec![":alice!alice@budgetchat PART #main".to_string()]
+        );
+        assert_eq!(
+            translate("[alice] [not] a name", "bob", "#main"),
+            vec![":alice!alice@budgetchat PRIVMSG #main :[not] a name".to_string()]
+        );
+        assert_eq!(
+            translate("* You are banned", "bob", "#main"),
+            vec![":budgetchat NOTICE bob :You are banned".to_string()]
+        );
+    }
+}
@@ -0,0 +1,768 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, RwLock,
+    },
+};
+use uuid::Uuid;
+
+use crate::util::{env_var, Result};
+
+use self::{
+    chat_log::{ChatLog, ChatLogConfig, Entry, Event},
+    moderation::{
+        contains_banned_word, AdminLockout, BanList, Command, ModerationConfig, RateLimit, Target,
+        TokenBucket,
+    },
+};
+
+pub mod chat_log;
+mod irc;
+pub mod moderation;
+mod websocket;
+
+const PREFIX: &str = "BUDGETCHAT";
+
+/// Messages queued for a user before they're dropped for not keeping up.
+const OUTBOX_SIZE: usize = 256;
+
+lazy_static! {
+    static ref USERNAME_RE: Regex = Regex::new("^[a-zA-Z0-9]+$").unwrap();
+}
+
+/// A connection a user can chat over: a raw TCP stream or the in-memory end
+/// of one of the gateway bridges.
+trait Socket: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static> Socket for T {}
+
+pub struct Config {
+    pub room: String,
+    pub websocket_port: Option<String>,
+    pub irc_port: Option<String>,
+    pub log: Option<ChatLogConfig>,
+    pub moderation: ModerationConfig,
+}
+
+struct Server {
+    room: String,
+    log: Option<ChatLog>,
+    moderation: ModerationConfig,
+    bans: BanList,
+    admin_lockout: AdminLockout,
+    users: Vec<User>,
+}
+
+struct User {
+    /// Messages for the user's writer task, so nothing is written to a
+    /// socket while the server is locked.
+    outbox: mpsc::Sender<Vec<u8>>,
+    username: String,
+    uuid: Uuid,
+    addr: SocketAddr,
+    bucket: Option<TokenBucket>,
+    warnings: u32,
+    is_admin: bool,
+    /// Dropped along with the user, which stops its listener task.
+    _removed: oneshot::Sender<()>,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let log = env_var::<String>("BUDGETCHAT_LOG_PATH").map(|path| {
+            let mut log = ChatLogConfig::new(path);
+            if let Some(max_bytes) = env_var("BUDGETCHAT_LOG_MAX_BYTES") {
+                log.max_bytes = max_bytes;
+            }
+            if let Some(max_files) = env_var("BUDGETCHAT_LOG_MAX_FILES") {
+                log.max_files = max_files;
+            }
+            log
+        });
+
+        let rate_limit = env_var::<f64>("BUDGETCHAT_RATE_PER_SEC").map(|per_second| RateLimit {
+            burst: env_var("BUDGETCHAT_RATE_BURST").unwrap_or(per_second * 2.0),
+            per_second,
+        });
+
+        let banned_words = env_var::<String>("BUDGETCHAT_BANNED_WORDS")
+            .map(|words| {
+                words
+                    .split(',')
+                    .map(|w| w.trim().to_string())
+                    .filter(|w| !w.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let moderation = ModerationConfig {
+            rate_limit,
+            max_warnings: env_var("BUDGETCHAT_MAX_WARNINGS").unwrap_or(3),
+            banned_words,
+            admin_secret: env_var("BUDGETCHAT_ADMIN_SECRET"),
+            // 0 turns the lockout off
+            max_admin_failures: Some(env_var("BUDGETCHAT_MAX_ADMIN_FAILURES").unwrap_or(3))
+                .filter(|failures| *failures > 0),
+            ban_list_path: env_var("BUDGETCHAT_BAN_LIST"),
+        };
+
+        Config {
+            room: env_var("BUDGETCHAT_ROOM").unwrap_or_else(|| "main".to_string()),
+            websocket_port: env_var("BUDGETCHAT_WEBSOCKET_PORT"),
+            irc_port: env_var("BUDGETCHAT_IRC_PORT"),
+            log,
+            moderation,
+        }
+    }
+}
+
+impl Server {
+    fn new(
+        room: String,
+        log: Option<ChatLog>,
+        moderation: ModerationConfig,
+        bans: BanList,
+    ) -> Server {
+        Server {
+            room,
+            log,
+            admin_lockout: AdminLockout::new(moderation.max_admin_failures),
+            moderation,
+            bans,
+            users: vec![],
+        }
+    }
+
+    /// Queues the room listing for the new user and announces them.
+    async fn add_user<S: Socket>(
+        &mut self,
+        username: String,
+        socket: BufReader<S>,
+        addr: SocketAddr,
+        server_lock: Arc<RwLock<Server>>,
+    ) -> Result<()> {
+        let (read_half, write_half) = tokio::io::split(socket);
+        let (outbox, queued) = mpsc::channel(OUTBOX_SIZE);
+        tokio::spawn(write_messages(write_half, queued));
+
+        let usernames = self.get_usernames();
+        outbox
+            .send(format!("* The room contains: {}\n", usernames).into_bytes())
+            .await?;
+
+        let (removed, on_removed) = oneshot::channel();
+        let mut user = User::new(username, outbox, addr, removed);
+        user.bucket = self.moderation.rate_limit.map(TokenBucket::new);
+        let username = user.username.clone();
+        let user_uuid = user.uuid;
+        self.users.push(user);
+
+        self.log(Entry::new(Event::Join, &self.room, &username, addr))
+            .await;
+
+        let message = format!("* {} has entered the room\n", username);
+        self.broadcast_message(&user_uuid, message.as_bytes()).await;
+
+        tokio::spawn(async move {
+            listen_for_messages(user_uuid, read_half, on_removed, server_lock).await;
+        });
+
+        Ok(())
+    }
+
+    /// Sends `message` to everyone but `sender`. Users whose sockets fail are
+    /// removed afterwards, and their departures are broadcast the same way
+    /// until no more writes fail.
+    async fn broadcast_message(&mut self, sender: &Uuid, message: &[u8]) {
+        let mut failed = self.send_to_all(sender, message).await;
+
+        while let Some(user_uuid) = failed.pop() {
+            if let Some(message) = self.take_user(&user_uuid).await {
+                failed.extend(self.send_to_all(&user_uuid, message.as_bytes()).await);
+            }
+        }
+    }
+
+    /// Queues `message` for everyone but `sender`, returning the users whose
+    /// sockets failed or who have fallen behind.
+    async fn send_to_all(&mut self, sender: &Uuid, message: &[u8]) -> Vec<Uuid> {
+        let mut failed = vec![];
+
+        for user in &self.users {
+            if user.uuid == *sender {
+                continue;
+            }
+
+            if let Err(e) = user.send(message) {
+                eprintln!(
+                    "[{}] Failed to write to {} ({}): {}",
+                    PREFIX, user.username, user.addr, e
+                );
+                failed.push(user.uuid);
+            }
+        }
+
+        failed
+    }
+
+    /// Queues `message` for a single user, removing them if it can't be.
+    async fn send_to(&mut self, user_uuid: &Uuid, message: &[u8]) {
+        let user = match self.users.iter().find(|u| u.uuid == *user_uuid) {
+            Some(user) => user,
+            None => return,
+        };
+
+        if let Err(e) = user.send(message) {
+            eprintln!(
+                "[{}] Failed to write to {} ({}): {}",
+                PREFIX, user.username, user.addr, e
+            );
+            self.remove_user(user_uuid).await;
+        }
+    }
+
+    /// Commands are charged against the rate limit like messages, so they
+    /// can't be used to flood the server.
+    async fn handle_message(&mut self, sender: &Uuid, message: String) {
+        if !self.take_token(sender) {
+            return self
+                .warn(sender, "you are sending messages too quickly")
+                .await;
+        }
+
+        if self.moderation.admin_secret.is_some() {
+            if let Some(command) = Command::parse(&message) {
+                return self.run_command(sender, command).await;
+            }
+        }
+
+        if contains_banned_word(&message, &self.moderation.banned_words) {
+            return self
+                .warn(sender, "that message contains a banned word")
+                .await;
+        }
+
+        self.broadcast_prefixed_message(sender, message).await
+    }
+
+    /// Spends one of the sender's tokens, returning false if they have none
+    /// left.
+    fn take_token(&mut self, sender: &Uuid) -> bool {
+        match self.users.iter_mut().find(|u| u.uuid == *sender) {
+            Some(User {
+                bucket: Some(bucket),
+                ..
+            }) => bucket.try_take(),
+            _ => true,
+        }
+    }
+
+    async fn warn(&mut self, user_uuid: &Uuid, reason: &str) {
+        let max_warnings = self.moderation.max_warnings;
+        let user = match self.users.iter_mut().find(|u| u.uuid == *user_uuid) {
+            Some(user) => user,
+            None => return,
+        };
+
+        user.warnings += 1;
+        if user.warnings > max_warnings {
+            return self.kick(user_uuid, "too many warnings").await;
+        }
+
+        let message = format!("* Warning {}/{}: {}\n", user.warnings, max_warnings, reason);
+        self.send_to(user_uuid, message.as_bytes()).await;
+    }
+
+    async fn run_command(&mut self, sender: &Uuid, command: Command) {
+        let user = match self.users.iter_mut().find(|u| u.uuid == *sender) {
+            Some(user) => user,
+            None => return,
+        };
+
+        let reply = match command {
+            Command::Admin(_) if self.admin_lockout.is_locked(user.addr.ip()) => {
+                "* Too many invalid admin secrets, try again later\n".to_string()
+            }
+            Command::Admin(secret) => {
+                if self.moderation.admin_secret.as_deref() == Some(secret.as_str()) {
+                    self.admin_lockout.clear(user.addr.ip());
+                    user.is_admin = true;
+                    "* You are now an admin\n".to_string()
+                } else if self.admin_lockout.record_failure(user.addr.ip()) {
+                    println!(
+                        "[{}] Locking {} out of admin after invalid secrets",
+                        PREFIX,
+                        user.addr.ip()
+                    );
+                    "* Invalid admin secret, try again later\n".to_string()
+                } else {
+                    "* Invalid admin secret\n".to_string()
+                }
+            }
+            _ if !user.is_admin => "* Only admins can do that\n".to_string(),
+            Command::Kick(target) => {
+                let count = self
+                    .kick_target(sender, &target, "kicked by an admin")
+                    .await;
+                format!("* Kicked {} user(s) matching {}\n", count, target)
+            }
+            Command::Ban(target) => {
+                if let Err(e) = self.bans.ban(target.clone()).await {
+                    eprintln!("[{}] Failed to persist ban of {}: {}", PREFIX, target, e);
+                }
+                let count = self
+                    .kick_target(sender, &target, "banned by an admin")
+                    .await;
+                format!("* Banned {}, kicked {} user(s)\n", target, count)
+            }
+        };
+
+        self.send_to(sender, reply.as_bytes()).await;
+    }
+
+    /// Kicks every user matching `target` other than `sender`, returning how
+    /// many were kicked.
+    async fn kick_target(&mut self, sender: &Uuid, target: &Target, reason: &str) -> usize {
+        let matching: Vec<Uuid> = self
+            .users
+            .iter()
+            .filter(|u| u.uuid != *sender && u.matches(target))
+            .map(|u| u.uuid)
+            .collect();
+
+        for uuid in &matching {
+            self.kick(uuid, reason).await;
+        }
+
+        matching.len()
+    }
+
+    async fn kick(&mut self, user_uuid: &Uuid, reason: &str) {
+        if let Some(user) = self.users.iter().find(|u| u.uuid == *user_uuid) {
+            println!(
+                "[{}] Kicking {} ({}): {}",
+                PREFIX, user.username, user.addr, reason
+            );
+
+            // best effort, the user is removed either way, and their writer
+            // task shuts the socket down once it's sent what's queued
+            let message = format!("* You have been kicked: {}\n", reason);
+            let _ = user.send(message.as_bytes());
+        }
+
+        self.remove_user(user_uuid).await;
+    }
+
+    async fn broadcast_prefixed_message(&mut self, sender: &Uuid, message: String) {
+        let sender_user = match self.users.iter().find(|u| u.uuid == *sender) {
+            Some(user) => user,
+            None => return,
+        };
+        let sender_username = sender_user.username.to_string();
+        let sender_addr = sender_user.addr;
+
+        let entry = Entry::new(Event::Message, &self.room, &sender_username, sender_addr)
+            .with_message(&message);
+        self.log(entry).await;
+
+        let message = format!("[{}] {}", sender_username, message);
+        self.broadcast_message(sender, message.as_bytes()).await;
+    }
+
+    /// Removes a user and announces that they left. Removing a user that is
+    /// already gone does nothing, so every user leaves exactly once.
+    async fn remove_user(&mut self, user_uuid: &Uuid) {
+        if let Some(message) = self.take_user(user_uuid).await {
+            self.broadcast_message(user_uuid, message.as_bytes()).await;
+        }
+    }
+
+    /// Removes a user without announcing it, returning the "has left" notice
+    /// to broadcast. Dropping the user also stops its listener task.
+    async fn take_user(&mut self, user_uuid: &Uuid) -> Option<String> {
+        let index = self.users.iter().position(|u| u.uuid == *user_uuid)?;
+        let removed_user = self.users.remove(index);
+
+        println!(
+            "[{}] {} ({}) left the room",
+            PREFIX, removed_user.username, removed_user.addr
+        );
+
+        let entry = Entry::new(
+            Event::Leave,
+            &self.room,
+            &removed_user.username,
+            removed_user.addr,
+        );
+        self.log(entry).await;
+
+        Some(format!("* {} has left the room\n", removed_user.username))
+    }
+
+    async fn log(&mut self, entry: Entry) {
+        if let Some(log) = &mut self.log {
+            if let Err(e) = log.append(&entry).await {
+                eprintln!("[{}] Failed to write chat log: {}", PREFIX, e);
+            }
+        }
+    }
+
+    fn get_usernames(&self) -> String {
+        self.users
+            .iter()
+            .map(|u| u.username.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+impl User {
+    fn new(
+        username: String,
+        outbox: mpsc::Sender<Vec<u8>>,
+        addr: SocketAddr,
+        removed: oneshot::Sender<()>,
+    ) -> User {
+        User {
+            outbox,
+            username,
+            uuid: Uuid::new_v4(),
+            addr,
+            bucket: None,
+            warnings: 0,
+            is_admin: false,
+            _removed: removed,
+        }
+    }
+
+    fn send(&self, message: &[u8]) -> std::result::Result<(), &'static str> {
+        self.outbox.try_send(message.to_vec()).map_err(|e| match e {
+            TrySendError::Full(_) => "too many messages queued",
+            TrySendError::Closed(_) => "socket closed",
+        })
+    }
+
+    fn matches(&self, target: &Target) -> bool {
+        match target {
+            Target::Ip(ip) => self.addr.ip() == *ip,
+            Target::Name(name) => self.username == *name,
+        }
+    }
+}
+
+pub async fn start(port: &str, config: Config) -> Result<()> {
+    let address = format!("0.0.0.0:{}", port);
+    let listener = TcpListener::bind(&address).await?;
+
+    println!("[{}] Server listening on {}", PREFIX, &address);
+
+    let log = match config.log {
+        Some(log_config) => Some(ChatLog::open(log_config).await?),
+        None => None,
+    };
+    let bans = BanList::load(config.moderation.ban_list_path.clone()).await?;
+    let server = Server::new(config.room, log, config.moderation, bans);
+    let server_lock = Arc::new(RwLock::new(server));
+
+    if let Some(websocket_port) = config.websocket_port {
+        let server_lock_clone = server_lock.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = websocket::start(&websocket_port, server_lock_clone).await {
+                eprintln!("[{}] WebSocket listener failed: {}", PREFIX, e);
+            }
+        });
+    }
+
+    if let Some(irc_port) = config.irc_port {
+        let server_lock_clone = server_lock.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = irc::start(&irc_port, server_lock_clone).await {
+                eprintln!("[{}] IRC listener failed: {}", PREFIX, e);
+            }
+        });
+    }
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let server_lock_clone = server_lock.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(socket, addr, server_lock_clone).await {
+                eprintln!("[{}] Error occurred: {}", PREFIX, e);
+            }
+        });
+    }
+}
+
+/// Prompts for and validates a username. The reader is kept by the caller so
+/// anything the client sent after its name stays buffered for the session.
+async fn get_username<S: Socket>(reader: &mut BufReader<S>) -> Result<String> {
+    reader
+        .write_all("Welcome to budgetchat! What shall I call you?\n".as_bytes())
+        .await?;
+    reader.flush().await?;
+
+    let mut name = String::new();
+    reader.read_line(&mut name).await?;
+    name = name.trim().to_string();
+
+    if !USERNAME_RE.is_match(&name) {
+        return Err(format!("Invalid username {:?}", name).into());
+    }
+
+    Ok(name)
+}
+
+async fn handle_connection<S: Socket>(
+    mut socket: S,
+    addr: SocketAddr,
+    server_lock: Arc<RwLock<Server>>,
+) -> Result<()> {
+    println!("[{}] Connection established from {}", PREFIX, addr);
+
+    if server_lock
+        .read()
+        .await
+        .bans
+        .is_banned(&Target::Ip(addr.ip()))
+    {
+        socket.write_all(b"* You are banned\n").await?;
+        return Err(format!("Rejected banned address {}", addr).into());
+    }
+
+    let mut reader = BufReader::new(socket);
+    let name = get_username(&mut reader).await?;
+
+    let mut server = server_lock.write().await;
+    if server.bans.is_banned(&Target::Name(name.clone())) {
+        reader.write_all(b"* You are banned\n").await?;
+        return Err(format!("Rejected banned username {:?}", name).into());
+    }
+
+    server
+        .add_user(name, reader, addr, server_lock.clone())
+        .await?;
+
+    Ok(())
+}
+
+/// Writes a user's queued messages to their socket, shutting it down once
+/// the user is removed and everything queued has been sent.
+async fn write_messages<W: AsyncWrite + Unpin>(mut socket: W, mut queued: mpsc::Receiver<Vec<u8>>) {
+    while let Some(message) = queued.recv().await {
+        let written = match socket.write_all(&message).await {
+            Ok(()) => socket.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            eprintln!("[{}] Failed to write to socket: {}", PREFIX, e);
+            return;
+        }
+    }
+
+    let _ = socket.shutdown().await;
+}
+
+/// Reads lines from a user until their socket closes or fails, or until the
+/// server removes them.
+async fn listen_for_messages<R: AsyncRead + Unpin>(
+    sender_uuid: Uuid,
+    socket: R,
+    mut on_removed: oneshot::Receiver<()>,
+    server_lock: Arc<RwLock<Server>>,
+) {
+    let mut reader = BufReader::new(socket);
+
+    loop {
+        let mut message = String::new();
+
+        let result = tokio::select! {
+            result = reader.read_line(&mut message) => result,
+            _ = &mut on_removed => return,
+        };
+
+        let mut server = server_lock.write().await;
+
+        match result {
+            Ok(0) => {
+                server.remove_user(&sender_uuid).await;
+                return;
+            }
+            Ok(_) => server.handle_message(&sender_uuid, message).await,
+            Err(e) => {
+                eprintln!("[{}] Failed to read from socket: {}", PREFIX, e);
+                server.remove_user(&sender_uuid).await;
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    fn test_server() -> Arc<RwLock<Server>> {
+        moderated_server(ModerationConfig::default())
+    }
+
+    fn moderated_server(moderation: ModerationConfig) -> Arc<RwLock<Server>> {
+        let server = Server::new("main".to_string(), None, moderation, BanList::default());
+
+        Arc::new(RwLock::new(server))
+    }
+
+    async fn connect(server_lock: &Arc<RwLock<Server>>, greeting: &str) -> BufReader<DuplexStream> {
+        let (client, socket) = tokio::io::duplex(1024);
+        let addr = "127.0.0.1:1234".parse().unwrap();
+
+        let mut client = BufReader::new(client);
+        client.write_all(greeting.as_bytes()).await.unwrap();
+
+        let _ = handle_connection(socket, addr, server_lock.clone()).await;
+
+        assert_eq!(
+            read_line(&mut client).await,
+            "Welcome to budgetchat! What shall I call you?\n"
+        );
+        client
+    }
+
+    async fn join(server_lock: &Arc<RwLock<Server>>, name: &str) -> BufReader<DuplexStream> {
+        let mut client = connect(server_lock, &format!("{}\n", name)).await;
+        assert!(read_line(&mut client)
+            .await
+            .starts_with("* The room contains:"));
+        client
+    }
+
+    async fn read_line(client: &mut BufReader<DuplexStream>) -> String {
+        let mut line = String::new();
+        client.read_line(&mut line).await.unwrap();
+        line
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_announced_once() {
+        let server_lock = test_server();
+        let mut alice = join(&server_lock, "alice").await;
+        let bob = join(&server_lock, "bob").await;
+        assert_eq!(read_line(&mut alice).await, "* bob has entered the room\n");
+
+        drop(bob);
+        assert_eq!(read_line(&mut alice).await, "* bob has left the room\n");
+
+        let _carol = join(&server_lock, "carol").await;
+        assert_eq!(
+            read_line(&mut alice).await,
+            "* carol has entered the room\n"
+        );
+        assert_eq!(server_lock.read().await.get_usernames(), "alice, carol");
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_evicts_failed_writers() {
+        let server_lock = test_server();
+        let mut alice = join(&server_lock, "alice").await;
+        let bob = join(&server_lock, "bob").await;
+        let mut carol = join(&server_lock, "carol").await;
+        assert_eq!(read_line(&mut alice).await, "* bob has entered the room\n");
+        assert_eq!(
+            read_line(&mut alice).await,
+            "* carol has entered the room\n"
+        );
+
+        drop(bob);
+        alice.write_all(b"hello\n").await.unwrap();
+
+        // bob is removed either by the failed broadcast or by his listener
+        // seeing EOF, but only announced once
+        let mut lines = vec![read_line(&mut carol).await, read_line(&mut carol).await];
+        lines.sort();
+        assert_eq!(lines, vec!["* bob has left the room\n", "[alice] hello\n"]);
+
+        let _dave = join(&server_lock, "dave").await;
+        assert_eq!(read_line(&mut carol).await, "* dave has entered the room\n");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_username_not_announced() {
+        let server_lock = test_server();
+        let mut alice = join(&server_lock, "alice").await;
+
+        let mut invalid = connect(&server_lock, "not valid!\n").await;
+        assert_eq!(read_line(&mut invalid).await, "");
+
+        let _bob = join(&server_lock, "bob").await;
+        assert_eq!(read_line(&mut alice).await, "* bob has entered the room\n");
+    }
+
+    #[tokio::test]
+    async fn test_message_sent_with_username_is_kept() {
+        let server_lock = test_server();
+        let mut alice = join(&server_lock, "alice").await;
+
+        let mut bob = connect(&server_lock, "bob\nhello\n").await;
+        assert_eq!(read_line(&mut bob).await, "* The room contains: alice\n");
+        assert_eq!(read_line(&mut alice).await, "* bob has entered the room\n");
+        assert_eq!(read_line(&mut alice).await, "[bob] hello\n");
+    }
+
+    #[tokio::test]
+    async fn test_commands_are_rate_limited_and_locked_out() {
+        let server_lock = moderated_server(ModerationConfig {
+            rate_limit: Some(RateLimit {
+                burst: 2.0,
+                per_second: 0.001,
+            }),
+            max_warnings: 10,
+            admin_secret: Some("hunter2".to_string()),
+            ..ModerationConfig::default()
+        });
+        let mut alice = join(&server_lock, "alice").await;
+
+        alice.write_all(b"/admin guess1\n").await.unwrap();
+        assert_eq!(read_line(&mut alice).await, "* Invalid admin secret\n");
+        alice.write_all(b"/kick bob\n").await.unwrap();
+        assert_eq!(read_line(&mut alice).await, "* Only admins can do that\n");
+        alice.write_all(b"/admin hunter2\n").await.unwrap();
+        assert_eq!(
+            read_line(&mut alice).await,
+            "* Warning 1/10: you are sending messages too quickly\n"
+        );
+
+        let server_lock = moderated_server(ModerationConfig {
+            admin_secret: Some("hunter2".to_string()),
+            max_admin_failures: Some(2),
+            ..ModerationConfig::default()
+        });
+        let mut bob = join(&server_lock, "bob").await;
+
+        bob.write_all(b"/admin guess1\n/admin guess2\n")
+            .await
+            .unwrap();
+        assert_eq!(read_line(&mut bob).await, "* Invalid admin secret\n");
+        assert_eq!(
+            read_line(&mut bob).await,
+            "* Invalid admin secret, try again later\n"
+        );
+
+        // the lockout is per address, so another connection doesn't reset it
+        let mut carol = join(&server_lock, "carol").await;
+        carol.write_all(b"/admin hunter2\n").await.unwrap();
+        assert_eq!(
+            read_line(&mut carol).await,
+            "* Too many invalid admin secrets, try again later\n"
+        );
+    }
+}
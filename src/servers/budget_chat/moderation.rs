@@ -0,0 +1,336 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::util::Result;
+
+/// How long an address stays locked out of `/admin` after too many wrong
+/// secrets.
+const ADMIN_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Default)]
+pub struct ModerationConfig {
+    pub rate_limit: Option<RateLimit>,
+    /// Number of warnings a user gets before being kicked.
+    pub max_warnings: u32,
+    pub banned_words: Vec<String>,
+    pub admin_secret: Option<String>,
+    /// Wrong admin secrets an address may send before it's locked out, or
+    /// `None` to never lock addresses out.
+    pub max_admin_failures: Option<u32>,
+    pub ban_list_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub burst: f64,
+    pub per_second: f64,
+}
+
+/// Classic token bucket: holds up to `burst` tokens, refilled continuously at
+/// `per_second`, and each message spends one token.
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Target {
+    Ip(IpAddr),
+    Name(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Admin(String),
+    Kick(Target),
+    Ban(Target),
+}
+
+/// Wrong `/admin` secrets per address, so a secret can't be brute forced
+/// by reconnecting.
+#[derive(Debug)]
+pub struct AdminLockout {
+    max_failures: Option<u32>,
+    failures: HashMap<IpAddr, (u32, Instant)>,
+}
+
+/// Set of banned names and addresses, optionally persisted one entry per
+/// line so bans survive restarts.
+#[derive(Debug, Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    targets: HashSet<Target>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> TokenBucket {
+        TokenBucket {
+            limit,
+            tokens: limit.burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn try_take(&mut self) -> bool {
+        self.try_take_at(Instant::now())
+    }
+
+    fn try_take_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.limit.per_second).min(self.limit.burst);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl AdminLockout {
+    pub fn new(max_failures: Option<u32>) -> AdminLockout {
+        AdminLockout {
+            max_failures,
+            failures: HashMap::new(),
+        }
+    }
+
+    pub fn is_locked(&mut self, ip: IpAddr) -> bool {
+        self.is_locked_at(ip, Instant::now())
+    }
+
+    /// Counts a wrong secret, returning whether the address is now locked.
+    pub fn record_failure(&mut self, ip: IpAddr) -> bool {
+        self.record_failure_at(ip, Instant::now())
+    }
+
+    pub fn clear(&mut self, ip: IpAddr) {
+        self.failures.remove(&ip);
+    }
+
+    fn is_locked_at(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let max_failures = match self.max_failures {
+            Some(max_failures) => max_failures,
+            None => return false,
+        };
+        let (count, last) = match self.failures.get(&ip) {
+            Some(failures) => *failures,
+            None => return false,
+        };
+
+        // failures are forgotten once the lockout has passed
+        if now.saturating_duration_since(last) >= ADMIN_LOCKOUT {
+            self.failures.remove(&ip);
+            return false;
+        }
+
+        count >= max_failures
+    }
+
+    fn record_failure_at(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let locked = self.is_locked_at(ip, now);
+        let failures = self.failures.entry(ip).or_insert((0, now));
+        if !locked {
+            failures.0 += 1;
+        }
+        failures.1 = now;
+
+        self.is_locked_at(ip, now)
+    }
+}
+
+impl Target {
+    fn parse(raw: &str) -> Option<Target> {
+        if raw.is_empty() {
+            return None;
+        }
+
+        Some(match raw.parse() {
+            Ok(ip) => Target::Ip(ip),
+            Err(_) => Target::Name(raw.to_string()),
+        })
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Ip(ip) => write!(f, "{}", ip),
+            Target::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Command {
+    /// Parses `/admin <secret>`, `/kick <name|ip>` and `/ban <name|ip>`.
+    /// Anything else is treated as a regular chat message.
+    pub fn parse(message: &str) -> Option<Command> {
+        let mut parts = message.trim().splitn(2, ' ');
+        let command = parts.next()?;
+        let argument = parts.next().unwrap_or("").trim();
+
+        match command {
+            "/admin" if !argument.is_empty() => Some(Command::Admin(argument.to_string())),
+            "/kick" => Target::parse(argument).map(Command::Kick),
+            "/ban" => Target::parse(argument).map(Command::Ban),
+            _ => None,
+        }
+    }
+}
+
+impl BanList {
+    /// Reads the bans persisted to `path`. A missing file has none yet, but
+    /// any other failure is an error rather than letting banned users back in.
+    pub async fn load(path: Option<PathBuf>) -> Result<BanList> {
+        let mut targets = HashSet::new();
+
+        if let Some(path) = &path {
+            match fs::read_to_string(path).await {
+                Ok(contents) => {
+                    targets.extend(contents.lines().filter_map(|l| Target::parse(l.trim())))
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(format!("Failed to read ban list {}: {}", path.display(), e).into())
+                }
+            }
+        }
+
+        Ok(BanList { path, targets })
+    }
+
+    pub fn is_banned(&self, target: &Target) -> bool {
+        self.targets.contains(target)
+    }
+
+    pub async fn ban(&mut self, target: Target) -> Result<()> {
+        if !self.targets.insert(target.clone()) {
+            return Ok(());
+        }
+
+        if let Some(path) = &self.path {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(format!("{}\n", target).as_bytes()).await?;
+            file.flush().await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Case-insensitive whole word match against the banned words list.
+pub fn contains_banned_word(message: &str, banned_words: &[String]) -> bool {
+    message
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| banned_words.iter().any(|b| b.eq_ignore_ascii_case(word)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            burst: 2.0,
+            per_second: 1.0,
+        });
+        let start = bucket.last_refill;
+
+        assert!(bucket.try_take_at(start));
+        assert!(bucket.try_take_at(start));
+        assert!(!bucket.try_take_at(start));
+
+        assert!(!bucket.try_take_at(start + Duration::from_millis(500)));
+        assert!(bucket.try_take_at(start + Duration::from_millis(1000)));
+
+        // refill is capped at the burst size
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.try_take_at(later));
+        assert!(bucket.try_take_at(later));
+        assert!(!bucket.try_take_at(later));
+    }
+
+    #[test]
+    fn test_admin_lockout() {
+        let mut lockout = AdminLockout::new(Some(2));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(!lockout.record_failure_at(ip, start));
+        assert!(lockout.record_failure_at(ip, start));
+        assert!(lockout.is_locked_at(ip, start + Duration::from_secs(60)));
+        assert!(!lockout.is_locked_at(other, start));
+
+        // more attempts while locked out push the lockout back
+        let later = start + ADMIN_LOCKOUT - Duration::from_secs(1);
+        assert!(lockout.record_failure_at(ip, later));
+        assert!(lockout.is_locked_at(ip, start + ADMIN_LOCKOUT));
+        assert!(!lockout.is_locked_at(ip, later + ADMIN_LOCKOUT));
+
+        let mut lockout = AdminLockout::new(None);
+        for _ in 0..10 {
+            assert!(!lockout.record_failure_at(ip, start));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_ban_list() {
+        let dir = std::env::temp_dir().join(format!("bans-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+
+        let path = dir.join("bans");
+        let mut bans = BanList::load(Some(path.clone())).await.unwrap();
+        bans.ban(Target::Name("alice".to_string())).await.unwrap();
+        let bans = BanList::load(Some(path)).await.unwrap();
+        assert!(bans.is_banned(&Target::Name("alice".to_string())));
+
+        // a directory can't be read as a list
+        assert!(BanList::load(Some(dir.clone())).await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            Command::parse("/admin hunter2\n"),
+            Some(Command::Admin("hunter2".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/kick bob"),
+            Some(Command::Kick(Target::Name("bob".to_string())))
+        );
+        assert_eq!(
+            Command::parse("/ban 10.0.0.1"),
+            Some(Command::Ban(Target::Ip("10.0.0.1".parse().unwrap())))
+        );
+        assert_eq!(Command::parse("/kick"), None);
+        assert_eq!(Command::parse("hello /kick bob"), None);
+    }
+
+    #[test]
+    fn test_contains_banned_word() {
+        let banned = vec!["darn".to_string()];
+
+        assert!(contains_banned_word("well DARN it\n", &banned));
+        assert!(contains_banned_word("darn!", &banned));
+        assert!(!contains_banned_word("darning socks", &banned));
+    }
+}
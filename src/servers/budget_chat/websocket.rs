@@ -0,0 +1,186 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::RwLock,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::util::Result;
+
+use super::{handle_connection, Server, PREFIX};
+
+/// Size of the in-memory pipe between a WebSocket and the chat server.
+const BRIDGE_BUFFER_SIZE: usize = 64 * 1024;
+
+pub async fn start(port: &str, server_lock: Arc<RwLock<Server>>) -> Result<()> {
+    let address = format!("0.0.0.0:{}", port);
+    let listener = TcpListener::bind(&address).await?;
+
+    println!("[{}] WebSocket listening on {}", PREFIX, &address);
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let server_lock_clone = server_lock.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_websocket(socket, addr, server_lock_clone).await {
+                eprintln!("[{}] WebSocket error occurred: {}", PREFIX, e);
+            }
+        });
+    }
+}
+
+/// Bridges a WebSocket onto an in-memory stream that is handed to the regular
+/// chat session, so WebSocket users go through the same prompt, moderation and
+/// broadcast paths as TCP users. Each text frame becomes one chat line and
+/// each line the server writes becomes one text frame.
+async fn handle_websocket(
+    socket: TcpStream,
+    addr: SocketAddr,
+    server_lock: Arc<RwLock<Server>>,
+) -> Result<()> {
+    let websocket = tokio_tungstenite::accept_async(socket).await?;
+    let (mut ws_writer, mut ws_reader) = websocket.split();
+
+    let (chat_side, bridge_side) = tokio::io::duplex(BRIDGE_BUFFER_SIZE);
+    let (bridge_reader, mut bridge_writer) = tokio::io::split(bridge_side);
+
+    let session = async {
+        if let Err(e) = handle_connection(chat_side, addr, server_lock).await {
+            eprintln!("[{}] Error occurred: {}", PREFIX, e);
+        }
+    };
+
+    let inbound = async {
+        while let Some(Ok(message)) = ws_reader.next().await {
+            let line = match message {
+                Message::Text(text) => text.replace(['\r', '\n'], " "),
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            if bridge_writer
+                .write_all(format!("{}\n", line).as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        // signal EOF so the chat session disconnects the user
+        let _ = bridge_writer.shutdown().await;
+    };
+
+    let outbound = async {
+        let mut lines = BufReader::new(bridge_reader).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            if ws_writer.send(Message::Text(line)).await.is_err() {
+                return;
+            }
+        }
+
+        let _ = ws_writer.close().await;
+    };
+
+    tokio::join!(session, inbound, outbound);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::{Sink, Stream};
+
+    use super::super::moderation::{BanList, ModerationConfig};
+    use super::*;
+
+    async fn next_text<S>(websocket: &mut S) -> String
+    where
+        S: Stream<Item = tokio_tungstenite::tungstenite::Result<Message>> + Unpin,
+    {
+        match tokio::time::timeout(Duration::from_secs(5), websocket.next()).await {
+            Ok(Some(Ok(Message::Text(text)))) => text,
+            other => panic!("Expected a text frame, got {:?}", other),
+        }
+    }
+
+    async fn send_text<S>(websocket: &mut S, text: &str)
+    where
+        S: Sink<Message> + Unpin,
+        S::Error: std::fmt::Debug,
+    {
+        websocket
+            .send(Message::Text(text.to_string()))
+            .await
+            .unwrap();
+    }
+
+    async fn read_line(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        tokio::time::timeout(Duration::from_secs(5), reader.read_line(&mut line))
+            .await
+            .unwrap()
+            .unwrap();
+        line
+    }
+
+    #[tokio::test]
+    async fn test_websocket_and_tcp_users_chat() {
+        let server = Server::new(
+            "main".to_string(),
+            None,
+            ModerationConfig::default(),
+            BanList::default(),
+        );
+        let server_lock = Arc::new(RwLock::new(server));
+
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp_addr = tcp_listener.local_addr().unwrap();
+        let ws_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_addr = ws_listener.local_addr().unwrap();
+
+        let tcp_server = server_lock.clone();
+        tokio::spawn(async move {
+            let (socket, addr) = tcp_listener.accept().await.unwrap();
+            handle_connection(socket, addr, tcp_server).await.unwrap();
+        });
+        tokio::spawn(async move {
+            let (socket, addr) = ws_listener.accept().await.unwrap();
+            handle_websocket(socket, addr, server_lock).await.unwrap();
+        });
+
+        let (mut alice, _) = tokio_tungstenite::connect_async(format!("ws://{}", ws_addr))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_text(&mut alice).await,
+            "Welcome to budgetchat! What shall I call you?"
+        );
+        send_text(&mut alice, "alice").await;
+        assert_eq!(next_text(&mut alice).await, "* The room contains: ");
+
+        let mut bob = BufReader::new(TcpStream::connect(tcp_addr).await.unwrap());
+        assert_eq!(
+            read_line(&mut bob).await,
+            "Welcome to budgetchat! What shall I call you?\n"
+        );
+        bob.write_all(b"bob\n").await.unwrap();
+        assert_eq!(read_line(&mut bob).await, "* The room contains: alice\n");
+        assert_eq!(next_text(&mut alice).await, "* bob has entered the room");
+
+        send_text(&mut alice, "hi bob").await;
+        assert_eq!(read_line(&mut bob).await, "[alice] hi bob\n");
+        bob.write_all(b"hi alice\n").await.unwrap();
+        assert_eq!(next_text(&mut alice).await, "[bob] hi alice");
+
+        alice.close(None).await.unwrap();
+        assert_eq!(read_line(&mut bob).await, "* alice has left the room\n");
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::fs;
+
+use crate::util::Result;
+
+use super::Deposit;
+
+const CSV_HEADER: &str = "timestamp,price";
+
+/// File format of a session dump, one deposit per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `timestamp,price` with a header line.
+    Csv,
+    /// `{"timestamp":...,"price":...}`
+    JsonLines,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "csv" => Some(Format::Csv),
+            "jsonl" => Some(Format::JsonLines),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::JsonLines => "jsonl",
+        }
+    }
+}
+
+pub fn encode(format: Format, deposits: &[Deposit]) -> Result<String> {
+    let mut lines = vec![];
+
+    if format == Format::Csv {
+        lines.push(CSV_HEADER.to_string());
+    }
+    for deposit in deposits {
+        lines.push(match format {
+            Format::Csv => format!("{},{}", deposit.timestamp, deposit.price),
+            Format::JsonLines => serde_json::to_string(deposit)?,
+        });
+    }
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Parses a dump in either format, telling them apart line by line.
+pub fn decode(contents: &str) -> Result<Vec<Deposit>> {
+    let mut deposits = vec![];
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line == CSV_HEADER {
+            continue;
+        }
+
+        let deposit = if line.starts_with('{') {
+            serde_json::from_str(line).ok()
+        } else {
+            line.split_once(',').and_then(|(timestamp, price)| {
+                Some(Deposit {
+                    timestamp: timestamp.trim().parse().ok()?,
+                    price: price.trim().parse().ok()?,
+                })
+            })
+        };
+
+        match deposit {
+            Some(deposit) => deposits.push(deposit),
+            None => {
+                return Err(format!("Invalid deposit on line {}: {:?}", number + 1, line).into())
+            }
+        }
+    }
+
+    Ok(deposits)
+}
+
+/// Writes a session's deposits to a new file in `dir` named after the time
+/// and client address, returning its path.
+pub async fn write(
+    dir: &Path,
+    format: Format,
+    addr: SocketAddr,
+    deposits: &[Deposit],
+) -> Result<PathBuf> {
+    fs::create_dir_all(dir).await?;
+
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let client = format!("{}-{}", addr.ip(), addr.port()).replace(':', "_");
+    let path = dir.join(format!("{}-{}.{}", millis, client, format.extension()));
+
+    let contents = encode(format, deposits)?;
+    fs::write(&path, contents).await?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let deposits = vec![
+            Deposit {
+                timestamp: 12345,
+                price: 101,
+            },
+            Deposit {
+                timestamp: -1,
+                price: i32::MIN,
+            },
+        ];
+
+        for format in [Format::Csv, Format::JsonLines] {
+            let contents = encode(format, &deposits).unwrap();
+            assert_eq!(decode(&contents).unwrap(), deposits);
+        }
+
+        assert_eq!(
+            encode(Format::Csv, &deposits).unwrap(),
+            "timestamp,price\n12345,101\n-1,-2147483648\n"
+        );
+        assert!(decode("timestamp,price\n1,2\n1;2\n").is_err());
+    }
+}
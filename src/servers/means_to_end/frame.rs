@@ -0,0 +1,73 @@
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::{decode_message, Message, Mode};
+
+/// Every message is an opcode and two big endian `i32`s.
+pub const FRAME_SIZE: usize = 9;
+
+#[derive(Debug)]
+pub enum Frame {
+    Message(Message),
+    /// A whole frame whose opcode isn't accepted in this mode. Frames are
+    /// fixed size, so the stream is still aligned after one.
+    BadOpcode(u8),
+    /// The client closed the connection partway through a frame, after
+    /// sending this many bytes of it.
+    Truncated(usize),
+    /// The client closed the connection between frames.
+    End,
+}
+
+/// Reads the next frame, telling a clean end of stream apart from a
+/// truncated frame. Only I/O errors are returned as errors.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    mode: Mode,
+) -> std::io::Result<Frame> {
+    let mut raw_message = [0; FRAME_SIZE];
+    let mut filled = 0;
+
+    while filled < FRAME_SIZE {
+        match reader.read(&mut raw_message[filled..]).await? {
+            0 if filled == 0 => return Ok(Frame::End),
+            0 => return Ok(Frame::Truncated(filled)),
+            n => filled += n,
+        }
+    }
+
+    Ok(match decode_message(&raw_message, mode) {
+        Ok(message) => Frame::Message(message),
+        Err(_) => Frame::BadOpcode(raw_message[0]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_frame() {
+        let mut stream: &[u8] = b"I\0\0\0\x01\0\0\0\x02X\0\0\0\0\0\0\0\0L\0\0\0\0\0\0\0\0Q\0\0";
+
+        assert!(matches!(
+            read_frame(&mut stream, Mode::Strict).await.unwrap(),
+            Frame::Message(Message::Insert(_))
+        ));
+        assert!(matches!(
+            read_frame(&mut stream, Mode::Strict).await.unwrap(),
+            Frame::BadOpcode(b'X')
+        ));
+        assert!(matches!(
+            read_frame(&mut stream, Mode::Strict).await.unwrap(),
+            Frame::BadOpcode(b'L')
+        ));
+        assert!(matches!(
+            read_frame(&mut stream, Mode::Strict).await.unwrap(),
+            Frame::Truncated(3)
+        ));
+        assert!(matches!(
+            read_frame(&mut stream, Mode::Strict).await.unwrap(),
+            Frame::End
+        ));
+    }
+}
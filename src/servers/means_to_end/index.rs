@@ -0,0 +1,454 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+const NIL: usize = usize::MAX;
+
+/// Aggregate of a set of prices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub count: u64,
+    pub sum: i64,
+    pub min: i32,
+    pub max: i32,
+}
+
+#[derive(Debug)]
+struct Node {
+    timestamp: i32,
+    priority: u64,
+    left: usize,
+    right: usize,
+    own: Summary,
+    subtree: Summary,
+    min_timestamp: i32,
+    max_timestamp: i32,
+}
+
+/// Prices ordered by timestamp in a treap, with each node caching the
+/// summary of its subtree so a range query only visits the O(log n) nodes
+/// along the range's two edges. Deposits sharing a timestamp share a node
+/// and are all counted. Every walk is iterative, so even a degenerate tree
+/// can't overflow the stack.
+#[derive(Debug)]
+pub struct TimeIndex {
+    nodes: Vec<Node>,
+    /// Every price deposited at each node's timestamp, by node, only kept
+    /// when medians are needed.
+    prices: Option<Vec<Vec<i32>>>,
+    root: usize,
+    seed: u64,
+}
+
+impl Summary {
+    pub const EMPTY: Summary = Summary {
+        count: 0,
+        sum: 0,
+        min: i32::MAX,
+        max: i32::MIN,
+    };
+
+    fn of(price: i32) -> Summary {
+        Summary {
+            count: 1,
+            sum: price as i64,
+            min: price,
+            max: price,
+        }
+    }
+
+    fn merge(self, other: Summary) -> Summary {
+        Summary {
+            count: self.count + other.count,
+            sum: self.sum + other.sum,
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+}
+
+impl Default for TimeIndex {
+    fn default() -> TimeIndex {
+        TimeIndex::new()
+    }
+}
+
+impl TimeIndex {
+    pub fn new() -> TimeIndex {
+        TimeIndex {
+            nodes: vec![],
+            prices: None,
+            root: NIL,
+            seed: RandomState::new().build_hasher().finish(),
+        }
+    }
+
+    /// An index that also keeps every price so it can answer `median`.
+    pub fn with_prices() -> TimeIndex {
+        TimeIndex {
+            prices: Some(vec![]),
+            ..TimeIndex::new()
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.summary_of(self.root).count
+    }
+
+    pub fn insert(&mut self, timestamp: i32, price: i32) {
+        self.insert_with(timestamp, price, false);
+    }
+
+    /// Inserts `price`, dropping any prices already at `timestamp`.
+    pub fn replace(&mut self, timestamp: i32, price: i32) {
+        self.insert_with(timestamp, price, true);
+    }
+
+    pub fn contains(&self, timestamp: i32) -> bool {
+        let mut node = self.root;
+
+        while node != NIL {
+            let n = &self.nodes[node];
+            node = match timestamp.cmp(&n.timestamp) {
+                std::cmp::Ordering::Less => n.left,
+                std::cmp::Ordering::Greater => n.right,
+                std::cmp::Ordering::Equal => return true,
+            };
+        }
+
+        false
+    }
+
+    /// Summary of the prices with `min_time <= timestamp <= max_time`.
+    pub fn summary(&self, min_time: i32, max_time: i32) -> Summary {
+        let mut summary = Summary::EMPTY;
+        let mut stack = vec![self.root];
+
+        while let Some(node) = stack.pop() {
+            if node == NIL {
+                continue;
+            }
+
+            let n = &self.nodes[node];
+            if n.max_timestamp < min_time || n.min_timestamp > max_time {
+                continue;
+            }
+            if min_time <= n.min_timestamp && n.max_timestamp <= max_time {
+                summary = summary.merge(n.subtree);
+                continue;
+            }
+
+            if (min_time..=max_time).contains(&n.timestamp) {
+                summary = summary.merge(n.own);
+            }
+            stack.extend([n.left, n.right]);
+        }
+
+        summary
+    }
+
+    /// Median of the prices in the range, averaging the middle two when
+    /// there's an even number. Unlike the other aggregates this has to visit
+    /// every price in the range, and it's `None` unless the index was built
+    /// `with_prices`.
+    pub fn median(&self, min_time: i32, max_time: i32) -> Option<i32> {
+        let all_prices = self.prices.as_ref()?;
+        let mut prices: Vec<i32> = vec![];
+        let mut stack = vec![self.root];
+
+        while let Some(node) = stack.pop() {
+            if node == NIL {
+                continue;
+            }
+
+            let n = &self.nodes[node];
+            if n.max_timestamp < min_time || n.min_timestamp > max_time {
+                continue;
+            }
+
+            if (min_time..=max_time).contains(&n.timestamp) {
+                prices.extend(&all_prices[node]);
+            }
+            stack.extend([n.left, n.right]);
+        }
+
+        if prices.is_empty() {
+            return None;
+        }
+
+        let middle = prices.len() / 2;
+        let odd = prices.len() % 2 == 1;
+        let (lower, upper, _) = prices.select_nth_unstable(middle);
+        let upper = *upper as i64;
+
+        if odd {
+            return Some(upper as i32);
+        }
+
+        let lower = lower.iter().copied().max().unwrap_or_default() as i64;
+        Some(((lower + upper) / 2) as i32)
+    }
+
+    /// Walks down to `timestamp`, then back up the recorded path, rotating
+    /// the new node up while its priority beats its parent's.
+    fn insert_with(&mut self, timestamp: i32, price: i32, replace: bool) {
+        let mut path = vec![];
+        let mut node = self.root;
+
+        while node != NIL && self.nodes[node].timestamp != timestamp {
+            path.push(node);
+            node = if timestamp < self.nodes[node].timestamp {
+                self.nodes[node].left
+            } else {
+                self.nodes[node].right
+            };
+        }
+
+        let mut child = if node == NIL {
+            let priority = self.next_priority();
+            self.nodes.push(Node {
+                timestamp,
+                priority,
+                left: NIL,
+                right: NIL,
+                own: Summary::of(price),
+                subtree: Summary::of(price),
+                min_timestamp: timestamp,
+                max_timestamp: timestamp,
+            });
+            if let Some(prices) = &mut self.prices {
+                prices.push(vec![price]);
+            }
+            self.nodes.len() - 1
+        } else {
+            let n = &mut self.nodes[node];
+            n.own = match replace {
+                true => Summary::of(price),
+                false => n.own.merge(Summary::of(price)),
+            };
+            if let Some(prices) = &mut self.prices {
+                if replace {
+                    prices[node].clear();
+                }
+                prices[node].push(price);
+            }
+            self.update(node);
+            node
+        };
+
+        while let Some(parent) = path.pop() {
+            let rotate = self.nodes[child].priority > self.nodes[parent].priority;
+
+            child = if timestamp < self.nodes[parent].timestamp {
+                self.nodes[parent].left = child;
+                match rotate {
+                    true => self.rotate_right(parent),
+                    false => parent,
+                }
+            } else {
+                self.nodes[parent].right = child;
+                match rotate {
+                    true => self.rotate_left(parent),
+                    false => parent,
+                }
+            };
+
+            self.update(child);
+        }
+
+        self.root = child;
+    }
+
+    fn rotate_right(&mut self, node: usize) -> usize {
+        let left = self.nodes[node].left;
+        self.nodes[node].left = self.nodes[left].right;
+        self.update(node);
+        self.nodes[left].right = node;
+        self.update(left);
+        left
+    }
+
+    fn rotate_left(&mut self, node: usize) -> usize {
+        let right = self.nodes[node].right;
+        self.nodes[node].right = self.nodes[right].left;
+        self.update(node);
+        self.nodes[right].left = node;
+        self.update(right);
+        right
+    }
+
+    fn update(&mut self, node: usize) {
+        let Node {
+            left,
+            right,
+            own,
+            timestamp,
+            ..
+        } = self.nodes[node];
+
+        let subtree = self
+            .summary_of(left)
+            .merge(own)
+            .merge(self.summary_of(right));
+        let min_timestamp = if left == NIL {
+            timestamp
+        } else {
+            self.nodes[left].min_timestamp
+        };
+        let max_timestamp = if right == NIL {
+            timestamp
+        } else {
+            self.nodes[right].max_timestamp
+        };
+
+        let node = &mut self.nodes[node];
+        node.subtree = subtree;
+        node.min_timestamp = min_timestamp;
+        node.max_timestamp = max_timestamp;
+    }
+
+    fn summary_of(&self, node: usize) -> Summary {
+        match node {
+            NIL => Summary::EMPTY,
+            _ => self.nodes[node].subtree,
+        }
+    }
+
+    /// splitmix64 from a random seed, so a client can't pick an insertion
+    /// order that degrades the tree into a chain.
+    fn next_priority(&mut self) -> u64 {
+        self.seed = self.seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::XorShift;
+
+    use super::*;
+
+    #[test]
+    fn test_matches_linear_scan() {
+        let mut index = TimeIndex::with_prices();
+        let mut deposits: Vec<(i32, i32)> = vec![];
+        let mut rng = XorShift(42);
+        let mut next = |bound: i64| (rng.next() % (2 * bound as u64)) as i64 - bound;
+
+        for i in 0..2000 {
+            // a narrow timestamp range so duplicates are common
+            let (timestamp, price) = (next(300) as i32, next(1000) as i32);
+            index.insert(timestamp, price);
+            deposits.push((timestamp, price));
+
+            if i % 7 == 0 {
+                let (min_time, max_time) = (next(400) as i32, next(400) as i32);
+                let prices: Vec<i32> = deposits
+                    .iter()
+                    .filter(|(t, _)| (min_time..=max_time).contains(t))
+                    .map(|(_, p)| *p)
+                    .collect();
+
+                let expected = Summary {
+                    count: prices.len() as u64,
+                    sum: prices.iter().map(|p| *p as i64).sum(),
+                    min: prices.iter().copied().min().unwrap_or(i32::MAX),
+                    max: prices.iter().copied().max().unwrap_or(i32::MIN),
+                };
+                assert_eq!(index.summary(min_time, max_time), expected);
+
+                let mut sorted = prices.clone();
+                sorted.sort();
+                let median = match sorted.len() {
+                    0 => None,
+                    n if n % 2 == 1 => Some(sorted[n / 2]),
+                    n => Some(((sorted[n / 2 - 1] as i64 + sorted[n / 2] as i64) / 2) as i32),
+                };
+                assert_eq!(index.median(min_time, max_time), median);
+            }
+        }
+
+        assert_eq!(index.len(), 2000);
+        assert!(index
+            .prices
+            .as_ref()
+            .is_some_and(|p| p.len() == index.nodes.len()));
+        assert!(deposits.iter().all(|(t, _)| index.contains(*t)));
+        assert!(!index.contains(1000));
+
+        // replacing drops every earlier price at that timestamp
+        let (timestamp, _) = deposits[0];
+        index.replace(timestamp, 5000);
+        let summary = index.summary(timestamp, timestamp);
+        assert_eq!((summary.count, summary.sum), (1, 5000));
+        let replaced = deposits.iter().filter(|(t, _)| *t == timestamp).count();
+        assert_eq!(
+            index.summary(i32::MIN, i32::MAX).count,
+            (deposits.len() - replaced + 1) as u64
+        );
+    }
+
+    /// Timestamps ranked like the priorities an index will hand out make
+    /// it a chain, which must still be walked without deep recursion.
+    #[test]
+    fn test_adversarial_insert_order() {
+        const COUNT: usize = 10_000;
+
+        let chain = |seed: u64| {
+            let mut probe = TimeIndex::new();
+            probe.seed = seed;
+            let priorities: Vec<u64> = (0..COUNT).map(|_| probe.next_priority()).collect();
+            let mut ranked: Vec<usize> = (0..COUNT).collect();
+            ranked.sort_by_key(|i| priorities[*i]);
+
+            let mut timestamps = vec![0; COUNT];
+            for (rank, i) in ranked.into_iter().enumerate() {
+                timestamps[i] = rank as i32;
+            }
+            timestamps
+        };
+
+        let depth = |index: &TimeIndex| {
+            let mut deepest = 0;
+            let mut stack = vec![(index.root, 1)];
+            while let Some((node, depth)) = stack.pop() {
+                if node != NIL {
+                    deepest = deepest.max(depth);
+                    let n = &index.nodes[node];
+                    stack.extend([(n.left, depth + 1), (n.right, depth + 1)]);
+                }
+            }
+            deepest
+        };
+
+        // a small stack so any recursion proportional to the depth overflows
+        std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let mut index = TimeIndex::with_prices();
+                for timestamp in chain(index.seed) {
+                    index.insert(timestamp, timestamp);
+                }
+                assert_eq!(depth(&index), COUNT);
+
+                let summary = index.summary(10, COUNT as i32 - 11);
+                assert_eq!(summary.count, COUNT as u64 - 20);
+                assert_eq!(index.median(0, COUNT as i32), Some(COUNT as i32 / 2 - 1));
+
+                // the order that would have worked against a fixed seed of
+                // 0 leaves a freshly seeded index balanced
+                let mut index = TimeIndex::new();
+                for timestamp in chain(0) {
+                    index.insert(timestamp, timestamp);
+                }
+                assert!(depth(&index) < 100);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
@@ -0,0 +1,775 @@
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::util::{env_var, Result};
+
+use self::{
+    dump::Format,
+    frame::Frame,
+    index::TimeIndex,
+    policy::{Duplicates, OnError, Policies, Violation},
+    store::{Series, Store},
+};
+
+pub mod dump;
+mod frame;
+pub mod index;
+pub mod policy;
+mod store;
+
+const PREFIX: &str = "MEANS2END";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Only `I` and `Q` as specified by Protohackers.
+    Strict,
+    /// Also accepts the range aggregate opcodes, see `Aggregate`.
+    Extended,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mode: Mode,
+    /// Directory of named series shared by all connections. When set, each
+    /// connection opens by naming its series instead of getting a fresh one.
+    pub store_path: Option<PathBuf>,
+    pub policies: Policies,
+    /// Directory each session's accepted deposits are written to when it
+    /// disconnects.
+    pub dump_path: Option<PathBuf>,
+    pub dump_format: Format,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let mode = match env_var::<String>("MEANSTOEND_MODE").as_deref() {
+            Some("extended") => Mode::Extended,
+            _ => Mode::Strict,
+        };
+
+        Config {
+            mode,
+            store_path: env_var("MEANSTOEND_STORE_PATH"),
+            policies: Policies::from_env(),
+            dump_path: env_var("MEANSTOEND_DUMP_PATH"),
+            dump_format: env_var::<String>("MEANSTOEND_DUMP_FORMAT")
+                .and_then(|name| Format::parse(&name))
+                .unwrap_or(Format::Csv),
+        }
+    }
+}
+
+pub async fn start(port: &str, config: Config) -> Result<()> {
+    let address = format!("0.0.0.0:{}", port);
+    let listener = TcpListener::bind(&address).await?;
+
+    println!("[{}] Server listening on {}", PREFIX, &address);
+
+    accept_connections(listener, config).await
+}
+
+/// Serves every connection `listener` accepts.
+pub async fn accept_connections(listener: TcpListener, config: Config) -> Result<()> {
+    let store = match &config.store_path {
+        Some(path) => Some(Arc::new(
+            Store::open(path, config.policies, config.mode).await?,
+        )),
+        None => None,
+    };
+
+    let config = Arc::new(config);
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let config = config.clone();
+        let store = store.clone();
+
+        tokio::spawn(async move {
+            handle_connection(socket, addr, config, store).await;
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deposit {
+    pub timestamp: i32,
+    pub price: i32,
+}
+
+#[derive(Debug)]
+struct Query {
+    min_time: i32,
+    max_time: i32,
+}
+
+/// What a query computes over the prices in its range. Every answer is a
+/// big endian `i32`, except `Sum` which is an `i64`, and an empty range
+/// answers 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Aggregate {
+    /// `Q`, the only query in strict mode.
+    Mean,
+    /// `L`
+    Min,
+    /// `H`
+    Max,
+    /// `M`, averaging the middle two prices for an even count.
+    Median,
+    /// `C`
+    Count,
+    /// `S`
+    Sum,
+}
+
+#[derive(Debug)]
+enum Message {
+    Insert(Deposit),
+    Query(Aggregate, Query),
+}
+
+struct Account {
+    deposits: TimeIndex,
+    policies: Policies,
+}
+
+/// Prices a connection works on, either its own or a named series in the
+/// store.
+enum Session {
+    Local(Account),
+    Shared(Arc<Series>),
+}
+
+impl Session {
+    /// Whether the deposit was stored rather than dropped as a duplicate.
+    async fn deposit(&mut self, deposit: Deposit) -> Result<bool> {
+        match self {
+            Session::Local(account) => account.deposit(deposit),
+            Session::Shared(series) => series.deposit(deposit).await,
+        }
+    }
+
+    async fn answer(&self, aggregate: Aggregate, query: &Query) -> Result<Vec<u8>> {
+        match self {
+            Session::Local(account) => account.answer(aggregate, query),
+            Session::Shared(series) => series.answer(aggregate, query).await,
+        }
+    }
+}
+
+impl Account {
+    /// Only extended mode can ask for a median, so only it keeps every
+    /// price around.
+    fn new(policies: Policies, mode: Mode) -> Account {
+        let deposits = match mode {
+            Mode::Strict => TimeIndex::new(),
+            Mode::Extended => TimeIndex::with_prices(),
+        };
+
+        Account { deposits, policies }
+    }
+
+    /// Whether the deposit was stored rather than dropped as a duplicate.
+    fn deposit(&mut self, deposit: Deposit) -> Result<bool> {
+        let accepted = self.accepts(&deposit)?;
+        if accepted {
+            self.store(deposit);
+        }
+
+        Ok(accepted)
+    }
+
+    /// Whether `deposit` should be stored under the duplicates policy.
+    fn accepts(&self, deposit: &Deposit) -> Result<bool> {
+        if !self.deposits.contains(deposit.timestamp) {
+            return Ok(true);
+        }
+
+        match self.policies.duplicates {
+            Duplicates::Keep | Duplicates::Replace => Ok(true),
+            Duplicates::Ignore => Ok(false),
+            Duplicates::Reject => Err(Violation::DuplicateTimestamp.into()),
+        }
+    }
+
+    fn store(&mut self, deposit: Deposit) {
+        match self.policies.duplicates {
+            Duplicates::Replace => self.deposits.replace(deposit.timestamp, deposit.price),
+            _ => self.deposits.insert(deposit.timestamp, deposit.price),
+        }
+    }
+
+    fn answer(&self, aggregate: Aggregate, query: &Query) -> Result<Vec<u8>> {
+        let (min_time, max_time) = self.policies.range(query.min_time, query.max_time)?;
+        let summary = self.deposits.summary(min_time, max_time);
+        let empty = summary.count == 0;
+
+        let answer = match aggregate {
+            Aggregate::Sum => return Ok(summary.sum.to_be_bytes().to_vec()),
+            _ if empty => 0,
+            Aggregate::Mean => {
+                println!("{} / {}", summary.sum, summary.count);
+                summary.sum / summary.count as i64
+            }
+            Aggregate::Min => summary.min as i64,
+            Aggregate::Max => summary.max as i64,
+            Aggregate::Median => self.deposits.median(min_time, max_time).unwrap_or(0) as i64,
+            Aggregate::Count => summary.count as i64,
+        };
+
+        Ok(self.policies.fit(answer)?.to_be_bytes().to_vec())
+    }
+}
+
+async fn handle_connection(
+    mut socket: TcpStream,
+    addr: SocketAddr,
+    config: Arc<Config>,
+    store: Option<Arc<Store>>,
+) {
+    println!("[{}] Connection established from {}", PREFIX, addr);
+
+    let (read_half, write_half) = socket.split();
+
+    let mut reader = BufReader::new(read_half);
+    let mut writer = BufWriter::new(write_half);
+
+    let mut session = match store {
+        Some(store) => match open_series(&mut reader, &store).await {
+            Ok((name, series)) => {
+                println!("[{}] {} opened series {}", PREFIX, addr, name);
+                Session::Shared(series)
+            }
+            Err(e) => {
+                eprintln!("Failed to open series: {}", e);
+                return;
+            }
+        },
+        None => Session::Local(Account::new(config.policies, config.mode)),
+    };
+
+    // only worth keeping when they'll be dumped
+    let mut deposits = config.dump_path.as_ref().map(|_| vec![]);
+    serve(
+        &mut reader,
+        &mut writer,
+        &mut session,
+        addr,
+        &config,
+        &mut deposits,
+    )
+    .await;
+
+    if let (Some(dir), Some(deposits)) = (&config.dump_path, &deposits) {
+        match dump::write(dir, config.dump_format, addr, deposits).await {
+            Ok(path) => println!(
+                "[{}] Dumped {} deposits to {}",
+                PREFIX,
+                deposits.len(),
+                path.display()
+            ),
+            Err(e) => eprintln!("Failed to dump deposits: {}", e),
+        }
+    }
+}
+
+/// Answers messages until the client disconnects or breaks the protocol,
+/// recording every accepted insert in `deposits` if given.
+async fn serve<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    reader: &mut R,
+    writer: &mut BufWriter<W>,
+    session: &mut Session,
+    addr: SocketAddr,
+    config: &Config,
+    deposits: &mut Option<Vec<Deposit>>,
+) {
+    loop {
+        let frame = match frame::read_frame(reader, config.mode).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                eprintln!("Failed to read from socket: {}", e);
+                return;
+            }
+        };
+        let truncated = matches!(frame, Frame::Truncated(_));
+        let answer_width = match &frame {
+            Frame::Message(Message::Query(Aggregate::Sum, _)) => Some(8),
+            Frame::Message(Message::Query(..)) => Some(4),
+            _ => None,
+        };
+
+        let result = match frame {
+            Frame::Message(Message::Insert(deposit)) => {
+                session.deposit(deposit).await.map(|stored| {
+                    if let (true, Some(deposits)) = (stored, deposits.as_mut()) {
+                        deposits.push(deposit);
+                    }
+                    None
+                })
+            }
+            Frame::Message(Message::Query(aggregate, query)) => {
+                session.answer(aggregate, &query).await.map(Some)
+            }
+            Frame::BadOpcode(opcode) => Err(Violation::UnknownOpcode(opcode).into()),
+            Frame::Truncated(received) => {
+                eprintln!(
+                    "[{}] {} closed after {} bytes of a message",
+                    PREFIX, addr, received
+                );
+                Err(Violation::TruncatedFrame.into())
+            }
+            Frame::End => {
+                println!("[{}] {} disconnected", PREFIX, addr);
+                return;
+            }
+        };
+
+        // the boxed error isn't `Send`, so it has to be gone before the
+        // reply is written
+        let reply =
+            match result.map_err(|e| (e.downcast_ref::<Violation>().copied(), e.to_string())) {
+                Ok(reply) => reply,
+                Err((violation, e)) => match (violation, config.policies.on_error) {
+                    (Some(violation), OnError::Reply) => {
+                        eprintln!("[{}] Rejected message from {}: {}", PREFIX, addr, violation);
+                        // a failed query's answer is replaced by an error of
+                        // the same width, so the client stays in step. Other
+                        // messages get no answer to replace
+                        answer_width.map(|width| {
+                            let mut reply = vec![0; width];
+                            reply[..2].copy_from_slice(&[b'E', violation.code()]);
+                            reply
+                        })
+                    }
+                    _ => {
+                        eprintln!("Failed to handle message: {}", e);
+                        return;
+                    }
+                },
+            };
+
+        if let Some(reply) = reply {
+            if let Err(e) = send_message(writer, &reply).await {
+                eprintln!("Failed to send message to socket: {}", e);
+                return;
+            }
+        }
+
+        if truncated {
+            return;
+        }
+    }
+}
+
+async fn open_series<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    store: &Store,
+) -> Result<(String, Arc<Series>)> {
+    let name = store::read_series_name(reader).await?;
+    let series = store.series(&name).await?;
+
+    Ok((name, series))
+}
+
+fn decode_message(raw_message: &[u8; 9], mode: Mode) -> Result<Message> {
+    let aggregate = match (raw_message[0], mode) {
+        (b'I', _) => {
+            return Ok(Message::Insert(Deposit {
+                timestamp: decode_int32(&raw_message[1..5])?,
+                price: decode_int32(&raw_message[5..9])?,
+            }))
+        }
+        (b'Q', _) => Aggregate::Mean,
+        (b'L', Mode::Extended) => Aggregate::Min,
+        (b'H', Mode::Extended) => Aggregate::Max,
+        (b'M', Mode::Extended) => Aggregate::Median,
+        (b'C', Mode::Extended) => Aggregate::Count,
+        (b'S', Mode::Extended) => Aggregate::Sum,
+        _ => return Err("Not supported".into()),
+    };
+
+    Ok(Message::Query(
+        aggregate,
+        Query {
+            min_time: decode_int32(&raw_message[1..5])?,
+            max_time: decode_int32(&raw_message[5..9])?,
+        },
+    ))
+}
+
+fn decode_int32(bytes: &[u8]) -> Result<i32> {
+    match bytes.try_into() {
+        Ok(bytes) => Ok(i32::from_be_bytes(bytes)),
+        _ => Err("Failed to parse i32".into()),
+    }
+}
+
+async fn send_message<W: AsyncWrite + Unpin>(
+    writer: &mut BufWriter<W>,
+    message: &[u8],
+) -> Result<()> {
+    writer.write_all(message).await?;
+    writer.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tokio::io::{AsyncReadExt, ReadBuf};
+
+    use crate::util::XorShift;
+
+    use super::frame::FRAME_SIZE;
+
+    use super::*;
+
+    fn message(opcode: u8, a: i32, b: i32) -> [u8; 9] {
+        let mut raw_message = [opcode; 9];
+        raw_message[1..5].copy_from_slice(&a.to_be_bytes());
+        raw_message[5..9].copy_from_slice(&b.to_be_bytes());
+        raw_message
+    }
+
+    #[test]
+    fn test_extended_opcodes_need_extended_mode() {
+        for opcode in [b'L', b'H', b'M', b'C', b'S'] {
+            assert!(decode_message(&message(opcode, 0, 1), Mode::Strict).is_err());
+            assert!(decode_message(&message(opcode, 0, 1), Mode::Extended).is_ok());
+        }
+        assert!(decode_message(&message(b'Q', 0, 1), Mode::Strict).is_ok());
+        assert!(decode_message(&message(b'X', 0, 1), Mode::Extended).is_err());
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let mut account = Account::new(Policies::default(), Mode::Extended);
+        for (timestamp, price) in [(12345, 101), (12346, 102), (12347, 100), (40960, 5)] {
+            account.deposit(Deposit { timestamp, price }).unwrap();
+        }
+
+        let answer = |opcode, min_time, max_time| match decode_message(
+            &message(opcode, min_time, max_time),
+            Mode::Extended,
+        ) {
+            Ok(Message::Query(aggregate, query)) => account.answer(aggregate, &query).unwrap(),
+            _ => panic!("not a query"),
+        };
+
+        assert_eq!(answer(b'Q', 12288, 16384), 101i32.to_be_bytes());
+        assert_eq!(answer(b'L', 12288, 16384), 100i32.to_be_bytes());
+        assert_eq!(answer(b'H', 0, 50000), 102i32.to_be_bytes());
+        assert_eq!(answer(b'M', 0, 50000), 100i32.to_be_bytes());
+        assert_eq!(answer(b'M', 12345, 12346), 101i32.to_be_bytes());
+        assert_eq!(answer(b'C', 0, 50000), 4i32.to_be_bytes());
+        assert_eq!(answer(b'S', 0, 50000), 308i64.to_be_bytes());
+
+        // empty and inverted ranges
+        for opcode in [b'Q', b'L', b'H', b'M', b'C'] {
+            assert_eq!(answer(opcode, 50000, 0), 0i32.to_be_bytes());
+        }
+        assert_eq!(answer(b'S', 50000, 0), 0i64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let prices = |duplicates| {
+            let mut account = Account::new(
+                Policies {
+                    duplicates,
+                    ..Policies::default()
+                },
+                Mode::Strict,
+            );
+            let results: Vec<bool> = [(1, 10), (1, 30), (2, 50)]
+                .into_iter()
+                .map(|(timestamp, price)| account.deposit(Deposit { timestamp, price }).is_ok())
+                .collect();
+            let mean = account.answer(
+                Aggregate::Mean,
+                &Query {
+                    min_time: 1,
+                    max_time: 1,
+                },
+            );
+            let count = account.answer(
+                Aggregate::Count,
+                &Query {
+                    min_time: 0,
+                    max_time: 9,
+                },
+            );
+
+            (results, mean.unwrap(), count.unwrap())
+        };
+
+        let ok = vec![true, true, true];
+        assert_eq!(
+            prices(Duplicates::Keep),
+            (
+                ok.clone(),
+                20i32.to_be_bytes().to_vec(),
+                3i32.to_be_bytes().to_vec()
+            )
+        );
+        assert_eq!(
+            prices(Duplicates::Ignore),
+            (
+                ok.clone(),
+                10i32.to_be_bytes().to_vec(),
+                2i32.to_be_bytes().to_vec()
+            )
+        );
+        assert_eq!(
+            prices(Duplicates::Replace),
+            (
+                ok,
+                30i32.to_be_bytes().to_vec(),
+                2i32.to_be_bytes().to_vec()
+            )
+        );
+        assert_eq!(
+            prices(Duplicates::Reject),
+            (
+                vec![true, false, true],
+                10i32.to_be_bytes().to_vec(),
+                2i32.to_be_bytes().to_vec()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_replies() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let policies = Policies {
+            duplicates: Duplicates::Reject,
+            inverted_range: policy::InvertedRange::Error,
+            on_error: OnError::Reply,
+            ..Policies::default()
+        };
+        tokio::spawn(async move {
+            let (socket, addr) = listener.accept().await.unwrap();
+            let config = Config {
+                mode: Mode::Extended,
+                store_path: None,
+                policies,
+                dump_path: None,
+                dump_format: Format::Csv,
+            };
+            handle_connection(socket, addr, Arc::new(config), None).await;
+        });
+
+        // pipelined, so answers must line up with the queries however many
+        // messages failed
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        for raw_message in [
+            message(b'I', 1, 10),
+            message(b'I', 1, 20),
+            message(b'X', 0, 0),
+            message(b'Q', 5, 0),
+            message(b'S', 5, 0),
+            message(b'Q', 0, 5),
+            message(b'S', 0, 5),
+        ] {
+            client.write_all(&raw_message).await.unwrap();
+        }
+
+        let code = Violation::InvertedRange.code();
+        let mut replies = [0; 24];
+        client.read_exact(&mut replies).await.unwrap();
+        assert_eq!(replies[..4], [b'E', code, 0, 0]);
+        assert_eq!(replies[4..12], [b'E', code, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(replies[12..16], 10i32.to_be_bytes());
+        assert_eq!(replies[16..], 10i64.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_dump_skips_ignored_duplicates() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dir = std::env::temp_dir().join(format!("dump-{}", uuid::Uuid::new_v4()));
+        let config = Config {
+            mode: Mode::Strict,
+            store_path: None,
+            policies: Policies {
+                duplicates: Duplicates::Ignore,
+                ..Policies::default()
+            },
+            dump_path: Some(dir.clone()),
+            dump_format: Format::Csv,
+        };
+        let server = tokio::spawn(async move {
+            let (socket, addr) = listener.accept().await.unwrap();
+            handle_connection(socket, addr, Arc::new(config), None).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        for raw_message in [
+            message(b'I', 1, 10),
+            message(b'I', 1, 20),
+            message(b'I', 2, 30),
+            message(b'Q', 0, 5),
+        ] {
+            client.write_all(&raw_message).await.unwrap();
+        }
+        let mut mean = [0; 4];
+        client.read_exact(&mut mean).await.unwrap();
+        assert_eq!(mean, 20i32.to_be_bytes());
+        drop(client);
+        server.await.unwrap();
+
+        let mut files = std::fs::read_dir(&dir).unwrap();
+        let path = files.next().unwrap().unwrap().path();
+        let dumped = dump::decode(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(
+            dumped,
+            [
+                Deposit {
+                    timestamp: 1,
+                    price: 10
+                },
+                Deposit {
+                    timestamp: 2,
+                    price: 30
+                },
+            ]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Plain list of deposits answering `Q` by linear scan.
+    #[derive(Default)]
+    struct Model {
+        deposits: Vec<(i32, i32)>,
+    }
+
+    impl Model {
+        fn mean(&self, min_time: i32, max_time: i32) -> i32 {
+            let prices: Vec<i64> = self
+                .deposits
+                .iter()
+                .filter(|(t, _)| (min_time..=max_time).contains(t))
+                .map(|(_, p)| *p as i64)
+                .collect();
+
+            match prices.len() {
+                0 => 0,
+                n => (prices.iter().sum::<i64>() / n as i64) as i32,
+            }
+        }
+    }
+
+    /// Hands out a stream a few bytes per read, like a socket might.
+    struct SplitReader {
+        data: Vec<u8>,
+        position: usize,
+        reads: Vec<usize>,
+    }
+
+    impl AsyncRead for SplitReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let size = self.reads.pop().unwrap_or(FRAME_SIZE);
+            let end = (self.position + size.min(buf.remaining())).min(self.data.len());
+            buf.put_slice(&self.data[self.position..end]);
+            self.position = end;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Feeds random streams of valid, unknown, raw random and truncated
+    /// frames to a session in randomly split reads and checks its replies
+    /// against the model, with errors either skipped or closing the
+    /// connection.
+    #[tokio::test]
+    async fn test_random_streams_match_model() {
+        let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+        let mut next = |bound: u64| rng.next() % bound;
+
+        for case in 0..200 {
+            let on_error = if case % 2 == 0 {
+                OnError::Reply
+            } else {
+                OnError::Close
+            };
+            let mut stream = vec![];
+            let mut expected = vec![];
+            let mut model = Model::default();
+            let mut open = true;
+
+            for _ in 0..next(40) {
+                let (a, b) = (next(64) as i32 - 32, next(2000) as i32 - 1000);
+                let (opcode, a, b) = match next(10) {
+                    0..=4 => (b'I', a, b),
+                    5..=7 => (b'Q', a, b),
+                    // anything but `I` and `Q` is unknown in strict mode
+                    8 => ([b'X', b'L', 0, 0xff][next(4) as usize], a, b),
+                    _ => {
+                        let raw = next(1 << 32) as u32 as i32;
+                        (next(256) as u8, raw, raw.wrapping_mul(31))
+                    }
+                };
+                stream.extend(message(opcode, a, b));
+
+                if !open {
+                    continue;
+                }
+                match opcode {
+                    b'I' => model.deposits.push((a, b)),
+                    b'Q' => expected.extend(model.mean(a, b).to_be_bytes()),
+                    // unknown opcodes get no answer to put an error in
+                    _ if on_error == OnError::Reply => {}
+                    _ => open = false,
+                }
+            }
+
+            // a partial message ends the connection without a reply
+            let tail = next(9) as usize;
+            stream.extend(&message(b'Q', 0, 0)[..tail]);
+
+            let config = Config {
+                mode: Mode::Strict,
+                store_path: None,
+                policies: Policies {
+                    on_error,
+                    ..Policies::default()
+                },
+                dump_path: None,
+                dump_format: Format::Csv,
+            };
+            let mut session = Session::Local(Account::new(config.policies, config.mode));
+            let mut writer = BufWriter::new(vec![]);
+            let addr = "127.0.0.1:0".parse().unwrap();
+
+            let mut reader = SplitReader {
+                reads: (0..stream.len()).map(|_| 1 + next(12) as usize).collect(),
+                data: stream,
+                position: 0,
+            };
+            serve(
+                &mut reader,
+                &mut writer,
+                &mut session,
+                addr,
+                &config,
+                &mut None,
+            )
+            .await;
+            assert_eq!(writer.into_inner(), expected, "case {}", case);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use crate::util::env_var;
+
+/// What to do with an insert at a timestamp that already has a price, which
+/// the spec leaves undefined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Duplicates {
+    /// Keep every price, so queries count all of them.
+    #[default]
+    Keep,
+    /// Keep the first price and drop later ones silently.
+    Ignore,
+    /// Keep only the latest price.
+    Replace,
+    /// Drop later prices and report `Violation::DuplicateTimestamp`.
+    Reject,
+}
+
+/// What to do with an answer that doesn't fit its `i32` on the wire. The
+/// mean of `i32` prices always fits, so in practice this is about `C`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Clamp to `i32::MIN` or `i32::MAX`.
+    #[default]
+    Saturate,
+    /// Keep the low 32 bits.
+    Truncate,
+    /// Report `Violation::Overflow`.
+    Error,
+}
+
+/// What to do with a query whose `min_time` comes after its `max_time`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvertedRange {
+    /// Treat it as an empty range and answer 0, as the spec requires.
+    #[default]
+    Empty,
+    /// Swap the bounds.
+    Swap,
+    /// Report `Violation::InvertedRange`.
+    Error,
+}
+
+/// How violations are reported to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnError {
+    /// Close the connection.
+    #[default]
+    Close,
+    /// Send `E` and the violation's code byte, then carry on.
+    Reply,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Policies {
+    pub duplicates: Duplicates,
+    pub overflow: Overflow,
+    pub inverted_range: InvertedRange,
+    pub on_error: OnError,
+}
+
+/// A request the server refuses to answer normally, either because of the
+/// configured policies or because it isn't a valid message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    DuplicateTimestamp,
+    InvertedRange,
+    Overflow,
+    UnknownOpcode(u8),
+    /// The connection closed partway through a message.
+    TruncatedFrame,
+}
+
+impl Policies {
+    pub fn from_env() -> Policies {
+        let defaults = Policies::default();
+
+        Policies {
+            duplicates: match env_var::<String>("MEANSTOEND_DUPLICATES").as_deref() {
+                Some("ignore") => Duplicates::Ignore,
+                Some("replace") => Duplicates::Replace,
+                Some("reject") => Duplicates::Reject,
+                _ => defaults.duplicates,
+            },
+            overflow: match env_var::<String>("MEANSTOEND_OVERFLOW").as_deref() {
+                Some("truncate") => Overflow::Truncate,
+                Some("error") => Overflow::Error,
+                _ => defaults.overflow,
+            },
+            inverted_range: match env_var::<String>("MEANSTOEND_INVERTED_RANGE").as_deref() {
+                Some("swap") => InvertedRange::Swap,
+                Some("error") => InvertedRange::Error,
+                _ => defaults.inverted_range,
+            },
+            on_error: match env_var::<String>("MEANSTOEND_ON_ERROR").as_deref() {
+                Some("reply") => OnError::Reply,
+                _ => defaults.on_error,
+            },
+        }
+    }
+
+    /// Applies the overflow policy to an answer sent as an `i32`.
+    pub fn fit(&self, value: i64) -> Result<i32, Violation> {
+        match (i32::try_from(value), self.overflow) {
+            (Ok(value), _) => Ok(value),
+            (Err(_), Overflow::Saturate) => {
+                Ok(value.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+            }
+            (Err(_), Overflow::Truncate) => Ok(value as i32),
+            (Err(_), Overflow::Error) => Err(Violation::Overflow),
+        }
+    }
+
+    /// Applies the inverted range policy, returning the bounds to query.
+    pub fn range(&self, min_time: i32, max_time: i32) -> Result<(i32, i32), Violation> {
+        match self.inverted_range {
+            _ if min_time <= max_time => Ok((min_time, max_time)),
+            InvertedRange::Empty => Ok((min_time, max_time)),
+            InvertedRange::Swap => Ok((max_time, min_time)),
+            InvertedRange::Error => Err(Violation::InvertedRange),
+        }
+    }
+}
+
+impl Violation {
+    pub fn code(&self) -> u8 {
+        match self {
+            Violation::DuplicateTimestamp => 1,
+            Violation::InvertedRange => 2,
+            Violation::Overflow => 3,
+            Violation::UnknownOpcode(_) => 4,
+            Violation::TruncatedFrame => 5,
+        }
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::DuplicateTimestamp => write!(f, "duplicate timestamp"),
+            Violation::InvertedRange => write!(f, "min_time is after max_time"),
+            Violation::Overflow => write!(f, "answer doesn't fit in an i32"),
+            Violation::UnknownOpcode(opcode) => write!(f, "unknown opcode {:?}", *opcode as char),
+            Violation::TruncatedFrame => write!(f, "connection closed partway through a message"),
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        let policies = |overflow| Policies {
+            overflow,
+            ..Policies::default()
+        };
+        let big = i32::MAX as i64 + 2;
+
+        assert_eq!(policies(Overflow::Error).fit(-7), Ok(-7));
+        assert_eq!(policies(Overflow::Saturate).fit(big), Ok(i32::MAX));
+        assert_eq!(policies(Overflow::Saturate).fit(-big), Ok(i32::MIN));
+        assert_eq!(policies(Overflow::Truncate).fit(big), Ok(i32::MIN + 1));
+        assert_eq!(policies(Overflow::Error).fit(big), Err(Violation::Overflow));
+    }
+
+    #[test]
+    fn test_range() {
+        let policies = |inverted_range| Policies {
+            inverted_range,
+            ..Policies::default()
+        };
+
+        assert_eq!(policies(InvertedRange::Error).range(1, 2), Ok((1, 2)));
+        assert_eq!(policies(InvertedRange::Empty).range(2, 1), Ok((2, 1)));
+        assert_eq!(policies(InvertedRange::Swap).range(2, 1), Ok((1, 2)));
+        assert_eq!(
+            policies(InvertedRange::Error).range(2, 1),
+            Err(Violation::InvertedRange)
+        );
+    }
+}
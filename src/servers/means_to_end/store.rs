@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    sync::{Mutex, RwLock},
+};
+
+use crate::util::Result;
+
+use super::{Account, Aggregate, Deposit, Mode, Policies, Query, PREFIX};
+
+/// Size of one `timestamp, price` record in a series file.
+const RECORD_SIZE: usize = 8;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Named price series shared by every connection and kept on disk as one
+/// append-only file of big endian `timestamp, price` records per series.
+pub struct Store {
+    dir: PathBuf,
+    policies: Policies,
+    mode: Mode,
+    series: Mutex<HashMap<String, Arc<Series>>>,
+}
+
+/// Inserts take the write lock for both the file append and the index
+/// update, so every reader sees inserts in the order they were persisted.
+pub struct Series {
+    state: RwLock<SeriesState>,
+}
+
+struct SeriesState {
+    account: Account,
+    file: File,
+}
+
+impl Store {
+    pub async fn open(dir: impl Into<PathBuf>, policies: Policies, mode: Mode) -> Result<Store> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).await?;
+
+        Ok(Store {
+            dir,
+            policies,
+            mode,
+            series: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the named series, loading it from disk the first time.
+    pub async fn series(&self, name: &str) -> Result<Arc<Series>> {
+        let mut series = self.series.lock().await;
+
+        if let Some(existing) = series.get(name) {
+            return Ok(existing.clone());
+        }
+
+        let path = self.dir.join(format!("{}.prices", name));
+        let loaded = Arc::new(Series::load(&path, self.policies, self.mode).await?);
+        series.insert(name.to_string(), loaded.clone());
+
+        Ok(loaded)
+    }
+}
+
+impl Series {
+    async fn load(path: &Path, policies: Policies, mode: Mode) -> Result<Series> {
+        let records = match fs::read(path).await {
+            Ok(records) => records,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+
+        // only accepted records are written, but the policy may have been
+        // tightened since, so replay them through it again
+        let mut account = Account::new(policies, mode);
+        let mut rejected = 0;
+        for record in records.chunks_exact(RECORD_SIZE) {
+            let deposit = Deposit {
+                timestamp: i32::from_be_bytes([record[0], record[1], record[2], record[3]]),
+                price: i32::from_be_bytes([record[4], record[5], record[6], record[7]]),
+            };
+            if account.accepts(&deposit).unwrap_or(false) {
+                account.store(deposit);
+            } else {
+                rejected += 1;
+            }
+        }
+
+        if rejected > 0 {
+            println!(
+                "[{}] Skipped {} of {} records in {} that the current policies reject",
+                PREFIX,
+                rejected,
+                records.len() / RECORD_SIZE,
+                path.display()
+            );
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        // drop a record left half written by a crash
+        let complete = records.len() - records.len() % RECORD_SIZE;
+        if complete < records.len() {
+            println!(
+                "[{}] Dropping {} trailing bytes from {}",
+                PREFIX,
+                records.len() - complete,
+                path.display()
+            );
+            file.set_len(complete as u64).await?;
+        }
+
+        Ok(Series {
+            state: RwLock::new(SeriesState { account, file }),
+        })
+    }
+
+    /// Whether the deposit was stored rather than dropped as a duplicate.
+    pub async fn deposit(&self, deposit: Deposit) -> Result<bool> {
+        let mut record = [0; RECORD_SIZE];
+        record[..4].copy_from_slice(&deposit.timestamp.to_be_bytes());
+        record[4..].copy_from_slice(&deposit.price.to_be_bytes());
+
+        let mut state = self.state.write().await;
+        if !state.account.accepts(&deposit)? {
+            return Ok(false);
+        }
+
+        state.file.write_all(&record).await?;
+        state.file.flush().await?;
+        state.account.store(deposit);
+
+        Ok(true)
+    }
+
+    pub async fn answer(&self, aggregate: Aggregate, query: &Query) -> Result<Vec<u8>> {
+        self.state.read().await.account.answer(aggregate, query)
+    }
+}
+
+/// Reads the `N`, length byte and name that open a connection in store
+/// mode. Names may only use ASCII letters, digits, `-` and `_`.
+pub async fn read_series_name<R: AsyncRead + Unpin>(reader: &mut R) -> Result<String> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header).await?;
+
+    if header[0] != b'N' {
+        return Err(format!("Expected a series name, got opcode {:?}", header[0] as char).into());
+    }
+
+    let mut name = vec![0; header[1] as usize];
+    reader.read_exact(&mut name).await?;
+
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .iter()
+            .all(|c| c.is_ascii_alphanumeric() || *c == b'-' || *c == b'_');
+    if !valid {
+        return Err(format!("Invalid series name {:?}", String::from_utf8_lossy(&name)).into());
+    }
+
+    Ok(String::from_utf8(name)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_series_survive_reopening() {
+        let dir = std::env::temp_dir().join(format!("means-to-end-{}", uuid::Uuid::new_v4()));
+        let mean = Query {
+            min_time: 0,
+            max_time: 100,
+        };
+
+        let store = Store::open(&dir, Policies::default(), Mode::Strict)
+            .await
+            .unwrap();
+        let series = store.series("btc").await.unwrap();
+        for (timestamp, price) in [(1, 10), (2, 20), (3, 60)] {
+            series.deposit(Deposit { timestamp, price }).await.unwrap();
+        }
+        // other names are separate series
+        let other = store.series("eth").await.unwrap();
+        other
+            .deposit(Deposit {
+                timestamp: 1,
+                price: 1,
+            })
+            .await
+            .unwrap();
+        drop((store, series, other));
+
+        // simulate a crash halfway through writing a record
+        let path = dir.join("btc.prices");
+        let mut file = OpenOptions::new().append(true).open(&path).await.unwrap();
+        file.write_all(&[0, 0, 0]).await.unwrap();
+        drop(file);
+
+        let store = Store::open(&dir, Policies::default(), Mode::Strict)
+            .await
+            .unwrap();
+        let series = store.series("btc").await.unwrap();
+        let answer = series.answer(Aggregate::Mean, &mean).await.unwrap();
+        assert_eq!(answer, 30i32.to_be_bytes());
+        assert_eq!(fs::metadata(&path).await.unwrap().len(), 24);
+
+        series
+            .deposit(Deposit {
+                timestamp: 4,
+                price: 30,
+            })
+            .await
+            .unwrap();
+        let answer = series.answer(Aggregate::Mean, &mean).await.unwrap();
+        assert_eq!(answer, 30i32.to_be_bytes());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_series_name() {
+        let read = |raw: &'static [u8]| async move { read_series_name(&mut &raw[..]).await };
+
+        assert_eq!(read(b"N\x03btc").await.unwrap(), "btc");
+        assert!(read(b"N\x00").await.is_err());
+        assert!(read(b"N\x05../ab").await.is_err());
+        assert!(read(b"I\x03btc").await.is_err());
+    }
+}
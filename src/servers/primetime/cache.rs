@@ -0,0 +1,105 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Rough per-entry bookkeeping cost on top of the key itself.
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Least recently used cache of primality results, keyed by the number's
+/// decimal digits and bounded by an estimate of the memory it uses.
+pub struct LruCache {
+    max_bytes: usize,
+    bytes: usize,
+    tick: u64,
+    entries: HashMap<String, (bool, u64)>,
+    /// Keys by the tick they were last used at, oldest first.
+    recency: BTreeMap<u64, String>,
+}
+
+impl LruCache {
+    pub fn new(max_bytes: usize) -> LruCache {
+        LruCache {
+            max_bytes,
+            bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<bool> {
+        let (value, used) = self.entries.get_mut(key)?;
+
+        self.tick += 1;
+        let key = self.recency.remove(used)?;
+        *used = self.tick;
+        self.recency.insert(self.tick, key);
+
+        Some(*value)
+    }
+
+    pub fn insert(&mut self, key: String, value: bool) {
+        let cost = entry_cost(&key);
+        if cost > self.max_bytes {
+            return;
+        }
+
+        self.tick += 1;
+        match self.entries.get_mut(&key) {
+            Some((old_value, used)) => {
+                *old_value = value;
+                self.recency.remove(used);
+                *used = self.tick;
+            }
+            None => {
+                self.entries.insert(key.clone(), (value, self.tick));
+                self.bytes += cost;
+            }
+        }
+        self.recency.insert(self.tick, key);
+
+        while self.bytes > self.max_bytes {
+            let (_, oldest) = match self.recency.pop_first() {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.entries.remove(&oldest);
+            self.bytes -= entry_cost(&oldest);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+fn entry_cost(key: &str) -> usize {
+    // the key is stored in both maps
+    key.len() * 2 + ENTRY_OVERHEAD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruCache::new(3 * entry_cost("1"));
+        cache.insert("1".to_string(), false);
+        cache.insert("2".to_string(), true);
+        cache.insert("3".to_string(), true);
+
+        // touching 1 makes 2 the oldest
+        assert_eq!(cache.get("1"), Some(false));
+        cache.insert("5".to_string(), true);
+
+        assert_eq!(cache.get("2"), None);
+        assert_eq!(cache.get("1"), Some(false));
+        assert_eq!(cache.get("3"), Some(true));
+        assert_eq!(cache.get("5"), Some(true));
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.bytes(), 3 * entry_cost("1"));
+    }
+}
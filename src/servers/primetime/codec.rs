@@ -0,0 +1,204 @@
+use serde::Serialize;
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use super::validate::{self, Malformed, Request};
+
+/// Largest frame accepted in any encoding, to stop a bogus length prefix or
+/// a line with no newline from allocating unbounded memory.
+const MAX_FRAME_BYTES: usize = 1024 * 1024;
+
+/// Line a client sends first to switch encodings when negotiation is on.
+pub const HANDSHAKE_PREFIX: &str = "ENCODING ";
+
+/// Wire encoding of Protohackers mode requests and responses. JSON is
+/// newline delimited, the binary encodings are framed by a big endian `u32`
+/// length prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+/// How each connection's encoding is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingPolicy {
+    Fixed(Encoding),
+    /// Clients may open with an `ENCODING <name>` line, JSON otherwise.
+    Negotiate,
+}
+
+pub enum Frame {
+    Data(Vec<u8>),
+    TooLarge(usize),
+    End,
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Encoding> {
+        match name {
+            "json" => Some(Encoding::Json),
+            "msgpack" => Some(Encoding::MessagePack),
+            "cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MessagePack => "msgpack",
+            Encoding::Cbor => "cbor",
+        }
+    }
+
+    pub async fn read_frame<R: AsyncBufRead + Unpin>(
+        self,
+        reader: &mut R,
+    ) -> std::io::Result<Frame> {
+        if self == Encoding::Json {
+            return read_line(reader).await;
+        }
+
+        let length = match reader.read_u32().await {
+            Ok(length) => length as usize,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(Frame::End),
+            Err(e) => return Err(e),
+        };
+        if length > MAX_FRAME_BYTES {
+            return Ok(Frame::TooLarge(length));
+        }
+
+        let mut frame = vec![0; length];
+        reader.read_exact(&mut frame).await?;
+        Ok(Frame::Data(frame))
+    }
+
+    /// Decodes and validates one request. Every encoding follows the same
+    /// rules, so `number` must be a native number even where that means a
+    /// binary client can't send integers past 64 bits.
+    pub fn decode_request(self, frame: &[u8]) -> Result<Request, Malformed> {
+        let request = match self {
+            Encoding::Json => return validate::parse_request(frame),
+            Encoding::MessagePack => rmp_serde::from_slice::<Value>(frame)
+                .map_err(|e| Malformed::InvalidFrame(e.to_string()))?,
+            Encoding::Cbor => ciborium::de::from_reader::<Value, _>(frame)
+                .map_err(|e| Malformed::InvalidFrame(e.to_string()))?,
+        };
+
+        validate::validate(request)
+    }
+
+    /// Encodes one response along with its delimiter or length prefix.
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        let body = match self {
+            Encoding::Json => {
+                let mut body = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+                body.push(b'\n');
+                return Ok(body);
+            }
+            Encoding::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string())?,
+            Encoding::Cbor => {
+                let mut body = vec![];
+                ciborium::ser::into_writer(value, &mut body).map_err(|e| e.to_string())?;
+                body
+            }
+        };
+
+        let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+        frame.extend(body);
+        Ok(frame)
+    }
+}
+
+/// Reads one newline delimited frame, giving up once it's longer than
+/// `MAX_FRAME_BYTES`.
+pub async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<Frame> {
+    let mut line = vec![];
+    let mut limited = reader.take(MAX_FRAME_BYTES as u64 + 1);
+
+    Ok(match limited.read_until(b'\n', &mut line).await? {
+        0 => Frame::End,
+        length if length > MAX_FRAME_BYTES => Frame::TooLarge(length),
+        _ => Frame::Data(line),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        methods::{self, Methods},
+        primality::Primality,
+    };
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_line_is_bounded() {
+        let mut input = &b"{}\n"[..];
+        assert!(matches!(read_line(&mut input).await, Ok(Frame::Data(line)) if line == b"{}\n"));
+        assert!(matches!(read_line(&mut input).await, Ok(Frame::End)));
+
+        let long = vec![b'1'; MAX_FRAME_BYTES * 2];
+        let mut input = &long[..];
+        assert!(matches!(
+            Encoding::Json.read_frame(&mut input).await,
+            Ok(Frame::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let big = "170141183460469231731687303715884105727";
+
+        for encoding in [Encoding::MessagePack, Encoding::Cbor] {
+            let request = encoding
+                .encode(&serde_json::json!({ "method": "nextPrime", "number": u64::MAX }))
+                .unwrap();
+            let request = encoding.decode_request(&request[4..]).unwrap();
+            assert_eq!(request.method, Methods::NextPrime);
+            assert_eq!(request.number.to_string(), "18446744073709551615");
+
+            let request = encoding
+                .encode(&serde_json::json!({ "method": "isPrime", "number": -2.5 }))
+                .unwrap();
+            assert_eq!(
+                encoding
+                    .decode_request(&request[4..])
+                    .unwrap()
+                    .number
+                    .to_string(),
+                "-2.5"
+            );
+
+            // numbers as strings are malformed, as they are in JSON
+            for number in ["seven", "7", big] {
+                let request = encoding
+                    .encode(&serde_json::json!({ "method": "isPrime", "number": number }))
+                    .unwrap();
+                assert_eq!(
+                    encoding.decode_request(&request[4..]),
+                    Err(Malformed::NotANumber)
+                );
+            }
+
+            let number = serde_json::from_str("18446744073709551615").unwrap();
+            let response =
+                methods::call(Methods::NextPrime, &number, &Primality::default()).unwrap();
+            let frame = encoding.encode(&response).unwrap();
+            assert_eq!(
+                u32::from_be_bytes(frame[..4].try_into().unwrap()) as usize,
+                frame.len() - 4
+            );
+
+            let decoded: Value = match encoding {
+                Encoding::MessagePack => rmp_serde::from_slice(&frame[4..]).unwrap(),
+                _ => ciborium::de::from_reader(&frame[4..]).unwrap(),
+            };
+            assert_eq!(
+                decoded,
+                serde_json::json!({ "method": "nextPrime", "prime": "18446744073709551629" })
+            );
+        }
+    }
+}
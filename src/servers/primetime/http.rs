@@ -0,0 +1,246 @@
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+use crate::{
+    http::{self, Request, Response},
+    util::Result,
+};
+
+use super::{
+    methods::{self, Methods},
+    primality::Primality,
+    spawn_on_pool,
+    validate::{self, Malformed},
+    Shared, PREFIX,
+};
+
+/// Serves `GET /<method>?n=<number>` and `POST /<method>` with a request
+/// object or a batch of them, e.g. `GET /isPrime?n=7`.
+pub async fn start(port: &str, shared: Arc<Shared>) -> Result<()> {
+    let address = format!("0.0.0.0:{}", port);
+    let listener = TcpListener::bind(&address).await?;
+
+    println!("[{}] HTTP listening on {}", PREFIX, &address);
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let shared = shared.clone();
+
+        tokio::spawn(async move {
+            let handler = |request| handle_request(request, shared.clone());
+            if let Err(e) = http::serve_connection(socket, handler).await {
+                eprintln!("[{}] HTTP error occurred with {}: {}", PREFIX, addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_request(request: Request, shared: Arc<Shared>) -> Response {
+    println!(
+        "[{}] HTTP {} {} {:?}",
+        PREFIX, request.method, request.path, request.query
+    );
+
+    let state = shared.clone();
+    match spawn_on_pool(&shared.workers, move || respond(&request, &state.primality)).await {
+        Ok(handle) => handle.await.unwrap_or_else(|e| {
+            eprintln!("Failed to handle request: {}", e);
+            error(500, &Malformed::Internal(e.to_string()))
+        }),
+        Err(e) => error(503, &Malformed::Internal(e.to_string())),
+    }
+}
+
+fn respond(request: &Request, primality: &Primality) -> Response {
+    let name = request.path.trim_start_matches('/');
+    let method = match serde_json::from_value::<Methods>(Value::String(name.to_string())) {
+        Ok(method) => method,
+        Err(_) => return error(404, &Malformed::UnknownMethod(name.to_string())),
+    };
+
+    let body = match request.method.as_str() {
+        "GET" => match request.query_param("n") {
+            // anything that isn't a JSON number is left as a string for the
+            // validator to reject, exactly like `"number":"7"` on the socket
+            Some(n) => serde_json::from_str::<Value>(n)
+                .ok()
+                .filter(Value::is_number)
+                .unwrap_or_else(|| Value::String(n.to_string())),
+            None => return error(400, &Malformed::MissingField("number")),
+        },
+        "POST" => match serde_json::from_slice::<Value>(&request.body) {
+            Ok(body) => body,
+            Err(_) => return error(400, &Malformed::InvalidJson),
+        },
+        _ => {
+            return Response::json(405, &json!({ "error": "method not allowed" }))
+                .with_header("Allow", "GET, POST")
+        }
+    };
+
+    match (request.method.as_str(), body) {
+        ("GET", number) => single(method, json!({ "number": number }), primality),
+        (_, Value::Array(batch)) => {
+            let results: Vec<Value> = batch
+                .into_iter()
+                .map(|request| match call(method, request, primality) {
+                    Ok(result) => result,
+                    Err(reason) => json!({ "error": reason.to_string() }),
+                })
+                .collect();
+
+            Response::json(200, &Value::Array(results))
+        }
+        (_, request) => single(method, request, primality),
+    }
+}
+
+fn single(method: Methods, request: Value, primality: &Primality) -> Response {
+    match call(method, request, primality) {
+        Ok(result) => Response::json(200, &result),
+        Err(reason) => error(400, &reason),
+    }
+}
+
+/// Validates and answers one request object the same way as the socket
+/// protocol, with the method taken from the path.
+fn call(
+    method: Methods,
+    mut request: Value,
+    primality: &Primality,
+) -> std::result::Result<Value, Malformed> {
+    if let Some(request) = request.as_object_mut() {
+        request.insert("method".to_string(), json!(method));
+    }
+
+    let request = validate::validate(request)?;
+    let response = methods::call_limited(request.method, &request.number, primality)
+        .map_err(|e| Malformed::InvalidParams(e.to_string()))?;
+
+    serde_json::to_value(response).map_err(|e| Malformed::Internal(e.to_string()))
+}
+
+fn error(status: u16, reason: &Malformed) -> Response {
+    Response::json(status, &json!({ "error": reason.to_string() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn request(method: &str, target: &str, body: &str) -> Response {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            target,
+            body.len(),
+            body
+        );
+        let mut reader = tokio::io::BufReader::new(raw.as_bytes());
+        let request = http::read_request(&mut reader).await.unwrap().unwrap();
+
+        respond(&request, &Primality::default())
+    }
+
+    fn assert_response(response: Response, status: u16, body: &str) {
+        let expected: Value = serde_json::from_str(body).unwrap();
+        let actual: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!((response.status, actual), (status, expected));
+    }
+
+    #[tokio::test]
+    async fn test_routes() {
+        let cases = [
+            (
+                "GET",
+                "/isPrime?n=7",
+                "",
+                200,
+                r#"{"method":"isPrime","prime":true}"#,
+            ),
+            (
+                "GET",
+                "/isPrime?n=1e3",
+                "",
+                200,
+                r#"{"method":"isPrime","prime":false}"#,
+            ),
+            (
+                "GET",
+                "/factorize?n=12",
+                "",
+                200,
+                r#"{"method":"factorize","factors":[2,2,3]}"#,
+            ),
+            (
+                "GET",
+                "/isPrime?n=%227%22",
+                "",
+                400,
+                r#"{"error":"number is not a JSON number"}"#,
+            ),
+            (
+                "GET",
+                "/isPrime?n=seven",
+                "",
+                400,
+                r#"{"error":"number is not a JSON number"}"#,
+            ),
+            (
+                "GET",
+                "/isPrime",
+                "",
+                400,
+                r#"{"error":"missing field \"number\""}"#,
+            ),
+            (
+                "GET",
+                "/isprime?n=7",
+                "",
+                404,
+                r#"{"error":"unknown method \"isprime\""}"#,
+            ),
+            (
+                "DELETE",
+                "/isPrime",
+                "",
+                405,
+                r#"{"error":"method not allowed"}"#,
+            ),
+            (
+                "POST",
+                "/isPrime",
+                r#"{"number":13}"#,
+                200,
+                r#"{"method":"isPrime","prime":true}"#,
+            ),
+            (
+                "POST",
+                "/isPrime",
+                "{",
+                400,
+                r#"{"error":"request is not valid JSON"}"#,
+            ),
+            (
+                "POST",
+                "/isPrime",
+                "7",
+                400,
+                r#"{"error":"request is not a JSON object"}"#,
+            ),
+            (
+                "POST",
+                "/isPrime",
+                r#"[{"number":13},{"number":"13"},{"method":"isPrime","number":4}]"#,
+                200,
+                r#"[{"method":"isPrime","prime":true},{"error":"number is not a JSON number"},{"method":"isPrime","prime":false}]"#,
+            ),
+        ];
+
+        for (method, target, body, status, expected) in cases {
+            assert_response(request(method, target, body).await, status, expected);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use serde_json::{json, Value};
+
+use super::{
+    methods::{self, MethodError, Methods},
+    primality::Primality,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+
+/// Handles one line holding a JSON-RPC 2.0 request or batch, returning the
+/// encoded response, or `None` when there is nothing to answer (a lone
+/// notification or a batch of them).
+pub fn handle_line(line: &str, primality: &Primality) -> Option<String> {
+    let response = match serde_json::from_str::<Value>(line) {
+        Ok(Value::Array(batch)) if batch.is_empty() => {
+            Some(error(Value::Null, INVALID_REQUEST, "Invalid Request"))
+        }
+        Ok(Value::Array(batch)) => {
+            let responses: Vec<Value> = batch
+                .into_iter()
+                .filter_map(|request| handle_request(request, primality))
+                .collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        Ok(request) => handle_request(request, primality),
+        Err(_) => Some(error(Value::Null, PARSE_ERROR, "Parse error")),
+    };
+
+    response.map(|r| r.to_string())
+}
+
+fn handle_request(request: Value, primality: &Primality) -> Option<Value> {
+    let mut request = match request {
+        Value::Object(request) => request,
+        _ => return Some(error(Value::Null, INVALID_REQUEST, "Invalid Request")),
+    };
+
+    let id = request.remove("id");
+    let valid_id = matches!(
+        id,
+        None | Some(Value::Null) | Some(Value::Number(_)) | Some(Value::String(_))
+    );
+    let valid_params = matches!(
+        request.get("params"),
+        None | Some(Value::Array(_)) | Some(Value::Object(_))
+    );
+    let method = match (request.get("jsonrpc"), request.get("method")) {
+        (Some(Value::String(version)), Some(Value::String(method)))
+            if version == "2.0" && valid_id && valid_params =>
+        {
+            method.clone()
+        }
+        _ => {
+            let id = id.filter(|_| valid_id).unwrap_or(Value::Null);
+            return Some(error(id, INVALID_REQUEST, "Invalid Request"));
+        }
+    };
+
+    let result = call(&method, request.remove("params"), primality);
+
+    // requests without an id are notifications and never answered
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err((code, message)) => error(id, code, &message),
+    })
+}
+
+fn call(
+    method: &str,
+    params: Option<Value>,
+    primality: &Primality,
+) -> Result<Value, (i64, String)> {
+    let method = serde_json::from_value::<Methods>(Value::String(method.to_string()))
+        .map_err(|_| (METHOD_NOT_FOUND, "Method not found".to_string()))?;
+
+    let number = match params {
+        Some(Value::Object(mut params)) if params.len() == 1 => params.remove("number"),
+        Some(Value::Array(mut params)) if params.len() == 1 => params.pop(),
+        _ => None,
+    };
+    let number = match number {
+        Some(Value::Number(number)) => number,
+        _ => {
+            let message = "Invalid params: expected a single number param";
+            return Err((INVALID_PARAMS, message.to_string()));
+        }
+    };
+
+    let response = methods::call_limited(method, &number, primality).map_err(|e| match e {
+        MethodError::InvalidParams(_) => (INVALID_PARAMS, e.to_string()),
+    })?;
+
+    // the method name is already in the request, only keep the payload
+    let mut result = serde_json::to_value(response).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+    if let Some(result) = result.as_object_mut() {
+        result.remove("method");
+    }
+
+    Ok(result)
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message },
+        "id": id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_response(request: &str, expected: Option<&str>) {
+        let response = handle_line(request, &Primality::default())
+            .map(|r| serde_json::from_str::<Value>(&r).unwrap());
+        let expected = expected.map(|e| serde_json::from_str::<Value>(e).unwrap());
+
+        assert_eq!(response, expected, "{}", request);
+    }
+
+    #[test]
+    fn test_requests() {
+        assert_response(
+            r#"{"jsonrpc":"2.0","id":1,"method":"isPrime","params":{"number":7}}"#,
+            Some(r#"{"jsonrpc":"2.0","id":1,"result":{"prime":true}}"#),
+        );
+        assert_response(
+            r#"{"jsonrpc":"2.0","id":"a","method":"factorize","params":[12]}"#,
+            Some(r#"{"jsonrpc":"2.0","id":"a","result":{"factors":[2,2,3]}}"#),
+        );
+        assert_response(r#"{"jsonrpc":"2.0","method":"isPrime","params":[7]}"#, None);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_response(
+            r#"{"jsonrpc":"2.0","id":1,"method":"isPrime""#,
+            Some(r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"Parse error"}}"#),
+        );
+        assert_response(
+            r#"{"jsonrpc":"1.0","id":1,"method":"isPrime","params":[7]}"#,
+            Some(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32600,"message":"Invalid Request"}}"#),
+        );
+        assert_response(
+            r#"{"jsonrpc":"2.0","id":1,"method":"isPrime","params":7}"#,
+            Some(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32600,"message":"Invalid Request"}}"#),
+        );
+        assert_response(
+            r#"{"jsonrpc":"2.0","id":1,"method":"isComposite","params":[7]}"#,
+            Some(
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"Method not found"}}"#,
+            ),
+        );
+        assert_response(
+            r#"{"jsonrpc":"2.0","id":1,"method":"isPrime","params":["7"]}"#,
+            Some(
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"Invalid params: expected a single number param"}}"#,
+            ),
+        );
+        assert_response(
+            r#"{"jsonrpc":"2.0","id":1,"method":"factorize","params":[-4]}"#,
+            Some(
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"Invalid params: factorize needs an integer from 1 to 2^64-1"}}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_batches() {
+        assert_response(
+            "[]",
+            Some(
+                r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32600,"message":"Invalid Request"}}"#,
+            ),
+        );
+        assert_response(
+            r#"[1, {"jsonrpc":"2.0","id":2,"method":"nextPrime","params":[7]}, {"jsonrpc":"2.0","method":"isPrime","params":[7]}]"#,
+            Some(
+                r#"[{"jsonrpc":"2.0","id":null,"error":{"code":-32600,"message":"Invalid Request"}},{"jsonrpc":"2.0","id":2,"result":{"prime":11}}]"#,
+            ),
+        );
+        assert_response(
+            r#"[{"jsonrpc":"2.0","method":"isPrime","params":[7]}]"#,
+            None,
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Number;
+
+use super::{
+    primality::Primality,
+    prime::{self, Decimal},
+};
+
+/// Longest integer `nextPrime` and `prevPrime` will search from.
+const MAX_SEARCH_DIGITS: usize = 100;
+
+/// Longest integer `isPrime` will test outside Protohackers mode.
+/// Miller-Rabin on anything much longer takes long enough to tie up a
+/// blocking thread.
+const MAX_PRIME_DIGITS: usize = 300;
+
+/// Largest bound `primeCount` will sieve up to.
+const MAX_PRIME_COUNT: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Methods {
+    IsPrime,
+    Factorize,
+    NextPrime,
+    PrevPrime,
+    PrimeCount,
+}
+
+/// One response shape per method. The method name is written first, so
+/// `isPrime` stays `{"method":"isPrime","prime":true}`.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "method", rename_all = "camelCase")]
+pub enum Response {
+    IsPrime {
+        prime: bool,
+    },
+    Factorize {
+        factors: Vec<u64>,
+    },
+    NextPrime {
+        #[serde(serialize_with = "serialize_number")]
+        prime: Number,
+    },
+    PrevPrime {
+        #[serde(serialize_with = "serialize_optional_number")]
+        prime: Option<Number>,
+    },
+    PrimeCount {
+        count: u64,
+    },
+}
+
+/// Sent in place of a [`Response`] when the params don't suit the method,
+/// carrying a JSON-RPC style error object.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ErrorResponse {
+    pub method: Methods,
+    pub error: ErrorObject,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ErrorObject {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MethodError {
+    InvalidParams(String),
+}
+
+impl std::fmt::Display for MethodError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MethodError::InvalidParams(message) => write!(f, "Invalid params: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for MethodError {}
+
+pub fn call(
+    method: Methods,
+    number: &Number,
+    primality: &Primality,
+) -> Result<Response, MethodError> {
+    let token = number.to_string();
+
+    match method {
+        Methods::IsPrime => Ok(Response::IsPrime {
+            prime: primality.is_prime_token(&token),
+        }),
+        Methods::Factorize => {
+            let n = integer(&token, 20)?
+                .to_u64()
+                .filter(|n| *n >= 1)
+                .ok_or_else(|| invalid("factorize needs an integer from 1 to 2^64-1"))?;
+
+            Ok(Response::Factorize {
+                factors: prime::factorize(n),
+            })
+        }
+        Methods::NextPrime => {
+            let n = integer(&token, MAX_SEARCH_DIGITS)?;
+            Ok(Response::NextPrime {
+                prime: to_number(prime::next_prime(&n)),
+            })
+        }
+        Methods::PrevPrime => {
+            let n = integer(&token, MAX_SEARCH_DIGITS)?;
+            Ok(Response::PrevPrime {
+                prime: prime::prev_prime(&n).map(to_number),
+            })
+        }
+        Methods::PrimeCount => {
+            let n = integer(&token, 20)?;
+            if n > MAX_PRIME_COUNT.into() {
+                return Err(invalid(&format!(
+                    "primeCount is limited to {}",
+                    MAX_PRIME_COUNT
+                )));
+            }
+
+            Ok(Response::PrimeCount {
+                count: n.to_u64().map(|n| primality.prime_count(n)).unwrap_or(0),
+            })
+        }
+    }
+}
+
+/// Whether a request may take long enough that it shouldn't run on the
+/// connection's task. Primality checks on anything that fits a `u64` are a
+/// handful of modular multiplications.
+pub fn is_expensive(method: Methods, number: &Number) -> bool {
+    match method {
+        Methods::IsPrime => number.as_u64().is_none() && number.as_i64().is_none(),
+        _ => true,
+    }
+}
+
+fn integer(token: &str, max_digits: usize) -> Result<num_bigint::BigInt, MethodError> {
+    Decimal::parse(token)
+        .and_then(|decimal| decimal.to_integer(max_digits))
+        .ok_or_else(|| {
+            invalid(&format!(
+                "number must be an integer of at most {} digits",
+                max_digits
+            ))
+        })
+}
+
+fn to_number(n: impl ToString) -> Number {
+    serde_json::from_str(&n.to_string()).expect("integers are valid JSON numbers")
+}
+
+/// JSON keeps numbers exact whatever their size. Binary encodings get native
+/// integers when they fit in 64 bits and decimal strings otherwise.
+fn serialize_number<S: Serializer>(number: &Number, serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        number.serialize(serializer)
+    } else if let Some(n) = number.as_u64() {
+        serializer.serialize_u64(n)
+    } else if let Some(n) = number.as_i64() {
+        serializer.serialize_i64(n)
+    } else {
+        serializer.serialize_str(&number.to_string())
+    }
+}
+
+fn serialize_optional_number<S: Serializer>(
+    number: &Option<Number>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match number {
+        Some(number) => serialize_number(number, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Like [`call`], but refusing to test integers of more than
+/// `MAX_PRIME_DIGITS` digits for `isPrime`, for the modes that don't have to
+/// answer like the Protohackers checker expects. Numbers that can't be prime
+/// are answered whatever their length.
+pub fn call_limited(
+    method: Methods,
+    number: &Number,
+    primality: &Primality,
+) -> Result<Response, MethodError> {
+    if method == Methods::IsPrime {
+        let digits = prime::candidate_digits(&number.to_string());
+        if digits.is_some_and(|digits| digits.len() > MAX_PRIME_DIGITS) {
+            return Err(invalid(&format!(
+                "isPrime is limited to integers of {} digits",
+                MAX_PRIME_DIGITS
+            )));
+        }
+    }
+
+    call(method, number, primality)
+}
+
+fn invalid(message: &str) -> MethodError {
+    MethodError::InvalidParams(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_json(method: Methods, number: &str) -> String {
+        let number = serde_json::from_str(number).unwrap();
+        serde_json::to_string(&call(method, &number, &Primality::default()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_response_shapes() {
+        assert_eq!(
+            call_json(Methods::IsPrime, "7"),
+            r#"{"method":"isPrime","prime":true}"#
+        );
+        assert_eq!(
+            call_json(Methods::Factorize, "12"),
+            r#"{"method":"factorize","factors":[2,2,3]}"#
+        );
+        assert_eq!(
+            call_json(Methods::NextPrime, "18446744073709551615"),
+            r#"{"method":"nextPrime","prime":18446744073709551629}"#
+        );
+        assert_eq!(
+            call_json(Methods::PrevPrime, "2"),
+            r#"{"method":"prevPrime","prime":null}"#
+        );
+        assert_eq!(
+            call_json(Methods::PrimeCount, "10"),
+            r#"{"method":"primeCount","count":4}"#
+        );
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        let number = |n: &str| serde_json::from_str::<Number>(n).unwrap();
+        let call = |method, n: &str| call(method, &number(n), &Primality::default());
+
+        assert!(call(Methods::Factorize, "0").is_err());
+        assert!(call(Methods::Factorize, "2.5").is_err());
+        assert!(call(Methods::NextPrime, "1e200").is_err());
+        assert!(call(Methods::PrimeCount, "1e12").is_err());
+        assert!(call(Methods::IsPrime, "2.5").is_ok());
+        assert!(call(Methods::IsPrime, "1e400").is_ok());
+    }
+
+    #[test]
+    fn test_prime_digit_limit() {
+        let number = |n: &str| serde_json::from_str::<Number>(n).unwrap();
+        let primality = Primality::default();
+        let not_prime = Ok(Response::IsPrime { prime: false });
+
+        let long = "7".repeat(MAX_PRIME_DIGITS + 1);
+        assert!(call_limited(Methods::IsPrime, &number(&long[1..]), &primality).is_ok());
+        assert!(call_limited(Methods::IsPrime, &number(&long), &primality).is_err());
+        assert!(call(Methods::IsPrime, &number(&long), &primality).is_ok());
+
+        // however long, these can't be prime
+        let negative = format!("-1.{}", "0".repeat(MAX_PRIME_DIGITS));
+        let fraction = format!("0.{}1", "0".repeat(MAX_PRIME_DIGITS));
+        let multiple_of_ten = format!("1{}", "0".repeat(MAX_PRIME_DIGITS));
+        for token in [negative, fraction, multiple_of_ten] {
+            assert!(token.len() > MAX_PRIME_DIGITS);
+            assert_eq!(
+                call(Methods::IsPrime, &number(&token), &primality),
+                not_prime
+            );
+            assert_eq!(
+                call_limited(Methods::IsPrime, &number(&token), &primality),
+                not_prime
+            );
+        }
+    }
+}
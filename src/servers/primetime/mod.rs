@@ -0,0 +1,678 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncWriteExt, BufReader, BufWriter},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::{
+        mpsc::{self, error::TryRecvError},
+        AcquireError, Semaphore,
+    },
+    task::JoinHandle,
+};
+
+use crate::util::{env_var, Result};
+
+use self::{
+    codec::{Encoding, EncodingPolicy, Frame, HANDSHAKE_PREFIX},
+    primality::Primality,
+    validate::{Malformed, Request},
+};
+
+mod cache;
+pub mod codec;
+mod http;
+mod jsonrpc;
+mod methods;
+pub mod primality;
+mod prime;
+mod sieve;
+mod validate;
+
+const PREFIX: &str = "PRIMETIME";
+
+/// How many requests a single connection may have in flight before we stop
+/// reading from it.
+const MAX_PIPELINED_REQUESTS: usize = 1024;
+
+/// What the spec suggests answering malformed requests with.
+const MALFORMED_RESPONSE: &[u8] = &[0, 1, 2, 3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Newline delimited `{"method":..,"number":..}` requests as specified by
+    /// Protohackers; malformed requests end the connection.
+    Protohackers,
+    /// Newline delimited JSON-RPC 2.0 requests and batches.
+    JsonRpc,
+}
+
+/// Body written before hanging up on a malformed Protohackers request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MalformedResponse {
+    /// Fixed bytes, `[0, 1, 2, 3]` unless configured otherwise.
+    Bytes(Vec<u8>),
+    /// An `{"error":..}` response saying why the request was rejected, in
+    /// the connection's encoding.
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mode: Mode,
+    /// Size of the blocking pool shared by all connections for expensive
+    /// requests.
+    pub workers: usize,
+    /// Precompute primality of every number up to this bound at startup.
+    pub sieve_limit: Option<u64>,
+    /// Memory budget for the cache of recent primality results.
+    pub cache_bytes: Option<usize>,
+    /// Log cache and sieve hit rates this often.
+    pub metrics_interval: Option<Duration>,
+    pub malformed_response: MalformedResponse,
+    /// Wire encoding for Protohackers mode; JSON-RPC is always JSON.
+    pub encoding: EncodingPolicy,
+    pub http_port: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let mode = match env_var::<String>("PRIMETIME_MODE").as_deref() {
+            Some("jsonrpc") => Mode::JsonRpc,
+            _ => Mode::Protohackers,
+        };
+
+        let workers = env_var("PRIMETIME_WORKERS").unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4)
+        });
+
+        Config {
+            mode,
+            workers,
+            sieve_limit: env_var("PRIMETIME_SIEVE_LIMIT"),
+            cache_bytes: env_var("PRIMETIME_CACHE_BYTES"),
+            // 0 turns reporting off, as a timer can't tick that often
+            metrics_interval: env_var("PRIMETIME_METRICS_INTERVAL")
+                .map(Duration::from_secs)
+                .filter(|interval| !interval.is_zero()),
+            malformed_response: MalformedResponse::from_env(),
+            encoding: match env_var::<String>("PRIMETIME_ENCODING").as_deref() {
+                Some("negotiate") => EncodingPolicy::Negotiate,
+                Some(name) => {
+                    EncodingPolicy::Fixed(Encoding::parse(name).unwrap_or(Encoding::Json))
+                }
+                None => EncodingPolicy::Fixed(Encoding::Json),
+            },
+            http_port: env_var("PRIMETIME_HTTP_PORT"),
+        }
+    }
+}
+
+impl MalformedResponse {
+    /// `PRIMETIME_MALFORMED_RESPONSE=json` explains the rejection, any other
+    /// value is written verbatim.
+    fn from_env() -> MalformedResponse {
+        match env_var::<String>("PRIMETIME_MALFORMED_RESPONSE") {
+            Some(body) if body == "json" => MalformedResponse::Json,
+            Some(body) => MalformedResponse::Bytes(body.into_bytes()),
+            None => MalformedResponse::Bytes(MALFORMED_RESPONSE.to_vec()),
+        }
+    }
+
+    fn body(&self, reason: &Malformed, encoding: Encoding) -> Vec<u8> {
+        match self {
+            MalformedResponse::Bytes(bytes) => bytes.clone(),
+            MalformedResponse::Json => encoding
+                .encode(&serde_json::json!({ "error": reason.to_string() }))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// State shared by every connection.
+struct Shared {
+    mode: Mode,
+    workers: Arc<Semaphore>,
+    primality: Primality,
+    malformed_response: MalformedResponse,
+    encoding: EncodingPolicy,
+}
+
+pub async fn start(port: &str, config: Config) -> Result<()> {
+    let address = format!("0.0.0.0:{}", port);
+    let listener = TcpListener::bind(&address).await?;
+
+    println!("[{}] Server listening on {}", PREFIX, &address);
+
+    // sieving to a large limit takes a while, so keep it off the runtime
+    let (sieve_limit, cache_bytes) = (config.sieve_limit, config.cache_bytes);
+    let primality =
+        tokio::task::spawn_blocking(move || Primality::new(sieve_limit, cache_bytes)).await?;
+    if primality.is_enabled() {
+        println!("[{}] Using {}", PREFIX, primality.describe());
+    }
+
+    let shared = Arc::new(Shared {
+        mode: config.mode,
+        workers: Arc::new(Semaphore::new(config.workers.max(1))),
+        primality,
+        malformed_response: config.malformed_response,
+        encoding: config.encoding,
+    });
+
+    if let Some(interval) = config.metrics_interval {
+        tokio::spawn(report_metrics(shared.clone(), interval));
+    }
+
+    if let Some(http_port) = config.http_port {
+        let shared = shared.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = http::start(&http_port, shared).await {
+                eprintln!("[{}] HTTP listener failed: {}", PREFIX, e);
+            }
+        });
+    }
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let shared = shared.clone();
+
+        tokio::spawn(async move {
+            handle_connection(socket, addr, shared).await;
+        });
+    }
+}
+
+async fn report_metrics(shared: Arc<Shared>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        println!("[{}] {}", PREFIX, shared.primality.metrics());
+    }
+}
+
+/// What to write back for one request line.
+#[derive(Debug)]
+enum Outcome {
+    Response(Vec<u8>),
+    /// JSON-RPC notifications get no response.
+    Nothing,
+    /// Protohackers mode answers malformed requests and hangs up.
+    Malformed(Malformed),
+}
+
+/// A request's outcome, either computed inline or still running on the
+/// blocking pool.
+enum Pending {
+    Ready(Outcome),
+    Running(JoinHandle<Outcome>),
+}
+
+/// Requests are read and dispatched as fast as they arrive, with expensive
+/// ones computed on the blocking pool, while a separate task writes the
+/// outcomes back in request order.
+async fn handle_connection(socket: TcpStream, addr: SocketAddr, shared: Arc<Shared>) {
+    println!("[{}] Connection established from {}", PREFIX, addr);
+
+    let (read_half, mut write_half) = socket.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    let (encoding, first_frame) = match negotiate(&mut reader, &mut write_half, &shared).await {
+        Some(negotiated) => negotiated,
+        None => return,
+    };
+
+    let (pending_tx, pending_rx) = mpsc::channel(MAX_PIPELINED_REQUESTS);
+
+    let writer = tokio::spawn(write_responses(
+        write_half,
+        shared.clone(),
+        encoding,
+        pending_rx,
+    ));
+    read_requests(reader, addr, shared, encoding, first_frame, pending_tx).await;
+
+    if let Err(e) = writer.await {
+        eprintln!("Response writer failed: {}", e);
+    }
+}
+
+/// Picks the connection's encoding. When negotiating, a first line that
+/// isn't an `ENCODING <name>` handshake is returned to be handled as the
+/// first JSON request.
+async fn negotiate(
+    reader: &mut BufReader<OwnedReadHalf>,
+    write_half: &mut OwnedWriteHalf,
+    shared: &Shared,
+) -> Option<(Encoding, Option<Vec<u8>>)> {
+    match (shared.mode, shared.encoding) {
+        (Mode::JsonRpc, _) => return Some((Encoding::Json, None)),
+        (_, EncodingPolicy::Fixed(encoding)) => return Some((encoding, None)),
+        (_, EncodingPolicy::Negotiate) => {}
+    }
+
+    let line = match codec::read_line(reader).await {
+        Ok(Frame::Data(line)) => line,
+        Ok(Frame::TooLarge(length)) => {
+            eprintln!("Handshake of {} bytes is too long", length);
+            return None;
+        }
+        Ok(Frame::End) => return None,
+        Err(e) => {
+            eprintln!("Failed to read from socket: {}", e);
+            return None;
+        }
+    };
+
+    let name = match std::str::from_utf8(&line)
+        .ok()
+        .and_then(|line| line.strip_prefix(HANDSHAKE_PREFIX))
+    {
+        Some(name) => name.trim(),
+        None => return Some((Encoding::Json, Some(line))),
+    };
+
+    let (response, encoding) = match Encoding::parse(name) {
+        Some(encoding) => {
+            let response = format!("{}{}\n", HANDSHAKE_PREFIX, encoding.name());
+            (response.into_bytes(), Some(encoding))
+        }
+        None => {
+            let reason = Malformed::UnknownEncoding(name.to_string());
+            eprintln!("Malformed handshake: {}", reason);
+            (
+                shared.malformed_response.body(&reason, Encoding::Json),
+                None,
+            )
+        }
+    };
+
+    if let Err(e) = write_half.write_all(&response).await {
+        eprintln!("Failed to send message to socket: {}", e);
+        return None;
+    }
+
+    encoding.map(|encoding| (encoding, None))
+}
+
+async fn read_requests(
+    mut reader: BufReader<OwnedReadHalf>,
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    encoding: Encoding,
+    mut first_frame: Option<Vec<u8>>,
+    pending_tx: mpsc::Sender<Pending>,
+) {
+    loop {
+        let frame = match first_frame.take() {
+            Some(frame) => frame,
+            None => match encoding.read_frame(&mut reader).await {
+                Ok(Frame::Data(frame)) => frame,
+                Ok(Frame::TooLarge(length)) => {
+                    let reason = Malformed::InvalidFrame(format!("{} bytes is too long", length));
+                    eprintln!("Malformed request from {}: {}", addr, reason);
+                    let _ = pending_tx
+                        .send(Pending::Ready(Outcome::Malformed(reason)))
+                        .await;
+                    return;
+                }
+                Ok(Frame::End) => return,
+                Err(e) => {
+                    eprintln!("Failed to read from socket: {}", e);
+                    return;
+                }
+            },
+        };
+
+        match encoding {
+            Encoding::Json => println!(
+                "[{}] Read message from {}: {}",
+                PREFIX,
+                addr,
+                String::from_utf8_lossy(&frame)
+            ),
+            _ => println!(
+                "[{}] Read {} byte {} frame from {}",
+                PREFIX,
+                frame.len(),
+                encoding.name(),
+                addr
+            ),
+        }
+
+        let pending = match shared.mode {
+            Mode::JsonRpc => {
+                let raw_request = String::from_utf8_lossy(&frame).into_owned();
+                let state = shared.clone();
+                run_on_pool(&shared.workers, move || {
+                    match jsonrpc::handle_line(&raw_request, &state.primality) {
+                        Some(response) => Outcome::Response(format!("{}\n", response).into_bytes()),
+                        None => Outcome::Nothing,
+                    }
+                })
+                .await
+            }
+            Mode::Protohackers => {
+                let request = encoding.decode_request(&frame);
+                if let Ok(Request { extra_fields, .. }) = &request {
+                    if !extra_fields.is_empty() {
+                        println!("[{}] Ignoring extra fields {:?}", PREFIX, extra_fields);
+                    }
+                }
+
+                match request {
+                    Ok(request) if methods::is_expensive(request.method, &request.number) => {
+                        let state = shared.clone();
+                        run_on_pool(&shared.workers, move || {
+                            handle_request(request, &state.primality, encoding)
+                        })
+                        .await
+                    }
+                    Ok(request) => {
+                        Pending::Ready(handle_request(request, &shared.primality, encoding))
+                    }
+                    Err(reason) => {
+                        eprintln!("Malformed request from {}: {}", addr, reason);
+                        Pending::Ready(Outcome::Malformed(reason))
+                    }
+                }
+            }
+        };
+
+        let is_malformed = matches!(pending, Pending::Ready(Outcome::Malformed(_)));
+
+        // the writer has hung up, nothing more will be answered
+        if pending_tx.send(pending).await.is_err() || is_malformed {
+            return;
+        }
+    }
+}
+
+/// Waits for the requests' outcomes in order and writes them, only flushing
+/// once no further outcomes are queued so pipelined responses are batched.
+async fn write_responses(
+    write_half: OwnedWriteHalf,
+    shared: Arc<Shared>,
+    encoding: Encoding,
+    mut pending_rx: mpsc::Receiver<Pending>,
+) {
+    let mut writer = BufWriter::new(write_half);
+
+    loop {
+        let pending = match pending_rx.try_recv() {
+            Ok(pending) => pending,
+            Err(TryRecvError::Empty) => {
+                if let Err(e) = writer.flush().await {
+                    eprintln!("Failed to send message to socket: {}", e);
+                    return;
+                }
+
+                match pending_rx.recv().await {
+                    Some(pending) => pending,
+                    None => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+
+        let outcome = match pending {
+            Pending::Ready(outcome) => outcome,
+            Pending::Running(handle) => handle.await.unwrap_or_else(|e| {
+                eprintln!("Failed to handle request: {}", e);
+                Outcome::Malformed(Malformed::Internal(e.to_string()))
+            }),
+        };
+
+        let result = match outcome {
+            Outcome::Response(raw_response) => writer.write_all(&raw_response).await,
+            Outcome::Nothing => Ok(()),
+            Outcome::Malformed(reason) => {
+                let body = shared.malformed_response.body(&reason, encoding);
+                if let Err(e) = writer.write_all(&body).await {
+                    eprintln!("Failed to send message to socket: {}", e);
+                }
+
+                if let Err(e) = writer.shutdown().await {
+                    eprintln!("Failed to shutdown socket: {}", e);
+                }
+
+                return;
+            }
+        };
+
+        if let Err(e) = result {
+            eprintln!("Failed to send message to socket: {}", e);
+            return;
+        }
+    }
+
+    if let Err(e) = writer.flush().await {
+        eprintln!("Failed to send message to socket: {}", e);
+    }
+}
+
+async fn run_on_pool<F>(workers: &Arc<Semaphore>, f: F) -> Pending
+where
+    F: FnOnce() -> Outcome + Send + 'static,
+{
+    match spawn_on_pool(workers, f).await {
+        Ok(handle) => Pending::Running(handle),
+        Err(e) => Pending::Ready(Outcome::Malformed(Malformed::Internal(e.to_string()))),
+    }
+}
+
+/// Runs `f` on the blocking pool once one of the shared workers is free.
+async fn spawn_on_pool<T, F>(
+    workers: &Arc<Semaphore>,
+    f: F,
+) -> std::result::Result<JoinHandle<T>, AcquireError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let permit = workers.clone().acquire_owned().await?;
+
+    Ok(tokio::task::spawn_blocking(move || {
+        let result = f();
+        drop(permit);
+        result
+    }))
+}
+
+/// Well formed requests with params the method can't take, like `factorize`
+/// of 0, are answered with a JSON-RPC style error and the connection stays
+/// open.
+fn handle_request(request: Request, primality: &Primality, encoding: Encoding) -> Outcome {
+    let encoded = match methods::call(request.method, &request.number, primality) {
+        Ok(response) => encoding.encode(&response),
+        Err(e) => {
+            eprintln!("Failed to handle request: {}", e);
+            encoding.encode(&methods::ErrorResponse {
+                method: request.method,
+                error: methods::ErrorObject {
+                    code: jsonrpc::INVALID_PARAMS,
+                    message: e.to_string(),
+                },
+            })
+        }
+    };
+
+    match encoded {
+        Ok(raw_response) => Outcome::Response(raw_response),
+        Err(e) => {
+            eprintln!("Failed encode response: {}", e);
+            Outcome::Malformed(Malformed::Internal(e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    async fn serve_one(
+        mode: Mode,
+        malformed_response: MalformedResponse,
+        encoding: EncodingPolicy,
+    ) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (socket, addr) = listener.accept().await.unwrap();
+            let shared = Arc::new(Shared {
+                mode,
+                workers: Arc::new(Semaphore::new(2)),
+                primality: Primality::new(Some(1000), Some(4096)),
+                malformed_response,
+                encoding,
+            });
+            handle_connection(socket, addr, shared).await;
+        });
+
+        TcpStream::connect(address).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_responses_keep_request_order() {
+        let default_response = MalformedResponse::Bytes(MALFORMED_RESPONSE.to_vec());
+        let json = EncodingPolicy::Fixed(Encoding::Json);
+        let mut client = serve_one(Mode::Protohackers, default_response, json).await;
+
+        let cases = [
+            ("170141183460469231731687303715884105727", true),
+            ("4", false),
+            ("7", true),
+            ("1e3", false),
+        ];
+        let mut requests = String::new();
+        let mut expected = String::new();
+        for (number, prime) in cases.iter().cycle().take(200) {
+            requests.push_str(&format!(
+                "{{\"method\":\"isPrime\",\"number\":{}}}\n",
+                number
+            ));
+            expected.push_str(&format!("{{\"method\":\"isPrime\",\"prime\":{}}}\n", prime));
+        }
+        requests.push_str("{}\n");
+        expected.push_str("\u{0}\u{1}\u{2}\u{3}");
+
+        client.write_all(requests.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert_eq!(response, expected);
+    }
+
+    #[tokio::test]
+    async fn test_json_malformed_response() {
+        // negotiation falls back to JSON without a handshake
+        let negotiate = EncodingPolicy::Negotiate;
+        let mut client = serve_one(Mode::Protohackers, MalformedResponse::Json, negotiate).await;
+
+        let requests = concat!(
+            "{\"method\":\"isPrime\",\"number\":7,\"extra\":true}\n",
+            "{\"method\":\"isPrime\",\"number\":\"7\"}\n",
+            "{\"method\":\"isPrime\",\"number\":11}\n",
+        );
+        client.write_all(requests.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert_eq!(
+            response,
+            concat!(
+                "{\"method\":\"isPrime\",\"prime\":true}\n",
+                "{\"error\":\"number is not a JSON number\"}\n",
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_params_keep_connection_open() {
+        let json = EncodingPolicy::Fixed(Encoding::Json);
+        let mut client = serve_one(Mode::Protohackers, MalformedResponse::Json, json).await;
+
+        // however long, a number that can't be prime is answered as usual
+        let requests = format!(
+            concat!(
+                "{{\"method\":\"factorize\",\"number\":0}}\n",
+                "{{\"method\":\"primeCount\",\"number\":1e12}}\n",
+                "{{\"method\":\"isPrime\",\"number\":-1.{}}}\n",
+                "{{\"method\":\"isPrime\",\"number\":7}}\n",
+            ),
+            "0".repeat(300)
+        );
+        client.write_all(requests.as_bytes()).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert_eq!(
+            response,
+            concat!(
+                "{\"method\":\"factorize\",\"error\":{\"code\":-32602,",
+                "\"message\":\"Invalid params: factorize needs an integer from 1 to 2^64-1\"}}\n",
+                "{\"method\":\"primeCount\",\"error\":{\"code\":-32602,",
+                "\"message\":\"Invalid params: primeCount is limited to 1000000000\"}}\n",
+                "{\"method\":\"isPrime\",\"prime\":false}\n",
+                "{\"method\":\"isPrime\",\"prime\":true}\n",
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_negotiated_msgpack() {
+        let negotiate = EncodingPolicy::Negotiate;
+        let mut client = serve_one(Mode::Protohackers, MalformedResponse::Json, negotiate).await;
+
+        #[derive(serde::Serialize)]
+        struct TestRequest<N> {
+            method: &'static str,
+            number: N,
+        }
+
+        // numbers must be native, a decimal string is malformed as in JSON
+        let encoding = Encoding::MessagePack;
+        let mut requests = b"ENCODING msgpack\n".to_vec();
+        for request in [
+            encoding.encode(&TestRequest {
+                method: "isPrime",
+                number: 7,
+            }),
+            encoding.encode(&TestRequest {
+                method: "nextPrime",
+                number: u64::MAX,
+            }),
+            encoding.encode(&TestRequest {
+                method: "isPrime",
+                number: "7",
+            }),
+        ] {
+            requests.extend(request.unwrap());
+        }
+        client.write_all(&requests).await.unwrap();
+
+        let mut response = vec![];
+        client.read_to_end(&mut response).await.unwrap();
+
+        let mut expected = b"ENCODING msgpack\n".to_vec();
+        for body in [
+            serde_json::json!({ "method": "isPrime", "prime": true }),
+            serde_json::json!({ "method": "nextPrime", "prime": "18446744073709551629" }),
+            serde_json::json!({ "error": "number is not a JSON number" }),
+        ] {
+            expected.extend(encoding.encode(&body).unwrap());
+        }
+        assert_eq!(response, expected);
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+use super::{cache::LruCache, prime, sieve::Sieve};
+
+/// Primality answers shared by every connection, backed by an optional
+/// precomputed sieve for small numbers and an optional LRU cache for the
+/// rest.
+#[derive(Default)]
+pub struct Primality {
+    sieve: Option<Sieve>,
+    cache: Option<Mutex<LruCache>>,
+    metrics: Metrics,
+}
+
+#[derive(Default)]
+struct Metrics {
+    lookups: AtomicU64,
+    sieve_hits: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl Primality {
+    pub fn new(sieve_limit: Option<u64>, cache_bytes: Option<usize>) -> Primality {
+        Primality {
+            sieve: sieve_limit.map(Sieve::new),
+            cache: cache_bytes.map(|bytes| Mutex::new(LruCache::new(bytes))),
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub fn is_prime_token(&self, token: &str) -> bool {
+        let digits = match prime::candidate_digits(token) {
+            Some(digits) => digits,
+            None => return false,
+        };
+
+        self.metrics.lookups.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(sieve) = &self.sieve {
+            if let Some(is_prime) = digits.parse().ok().and_then(|n| sieve.is_prime(n)) {
+                self.metrics.sieve_hits.fetch_add(1, Ordering::Relaxed);
+                return is_prime;
+            }
+        }
+
+        // anything that fits a u64 is cheaper to test than to look up
+        if let Ok(n) = digits.parse::<u64>() {
+            return prime::is_prime_u64(n);
+        }
+
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return prime::is_prime_digits(&digits),
+        };
+
+        if let Some(is_prime) = cache.lock().unwrap().get(&digits) {
+            self.metrics.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return is_prime;
+        }
+        self.metrics.cache_misses.fetch_add(1, Ordering::Relaxed);
+
+        // computed without holding the lock, a racing miss just does the
+        // same work twice
+        let is_prime = prime::is_prime_digits(&digits);
+        cache.lock().unwrap().insert(digits, is_prime);
+
+        is_prime
+    }
+
+    pub fn prime_count(&self, n: u64) -> u64 {
+        self.sieve
+            .as_ref()
+            .and_then(|sieve| sieve.prime_count(n))
+            .unwrap_or_else(|| prime::prime_count(n))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sieve.is_some() || self.cache.is_some()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+
+        if let Some(sieve) = &self.sieve {
+            parts.push(format!(
+                "sieve up to {} ({} bytes)",
+                sieve.limit(),
+                sieve.size_in_bytes()
+            ));
+        }
+
+        if let Some(cache) = &self.cache {
+            let cache = cache.lock().unwrap();
+            parts.push(format!(
+                "cache of {} entries ({} bytes)",
+                cache.len(),
+                cache.bytes()
+            ));
+        }
+
+        parts.join(", ")
+    }
+
+    /// Summarizes how lookups were answered since startup.
+    pub fn metrics(&self) -> String {
+        let lookups = self.metrics.lookups.load(Ordering::Relaxed);
+        let sieve_hits = self.metrics.sieve_hits.load(Ordering::Relaxed);
+        let cache_hits = self.metrics.cache_hits.load(Ordering::Relaxed);
+        let cache_misses = self.metrics.cache_misses.load(Ordering::Relaxed);
+
+        format!(
+            "{} lookups, sieve hits {}, cache hits {} / misses {} (hit rate {}), {}",
+            lookups,
+            sieve_hits,
+            cache_hits,
+            cache_misses,
+            percentage(cache_hits, cache_hits + cache_misses),
+            self.describe()
+        )
+    }
+}
+
+fn percentage(part: u64, total: u64) -> String {
+    if total == 0 {
+        return "n/a".to_string();
+    }
+
+    format!("{:.1}%", part as f64 * 100.0 / total as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookups_use_sieve_then_cache() {
+        let primality = Primality::new(Some(1000), Some(1024));
+
+        assert!(primality.is_prime_token("997"));
+        assert!(!primality.is_prime_token("1e2"));
+        // u64s skip the cache
+        assert!(primality.is_prime_token("2305843009213693951"));
+        assert!(primality.is_prime_token("618970019642690137449562111"));
+        assert!(primality.is_prime_token("618970019642690137449562111"));
+        assert!(!primality.is_prime_token("618970019642690137449562113"));
+
+        let metrics = &primality.metrics;
+        assert_eq!(metrics.lookups.load(Ordering::Relaxed), 5);
+        assert_eq!(metrics.sieve_hits.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.cache_hits.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.cache_misses.load(Ordering::Relaxed), 2);
+
+        assert_eq!(primality.prime_count(1000), 168);
+        assert_eq!(primality.prime_count(10_000), 1229);
+    }
+}
@@ -0,0 +1,641 @@
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::{One, ToPrimitive, Zero};
+
+/// Bases that make Miller-Rabin deterministic for every `u64`.
+const U64_BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+/// Primes tried as divisors above `u64` before the Baillie-PSW test.
+const SMALL_PRIMES: [u64; 20] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+];
+
+/// A JSON number token decoded exactly as `digits * 10^exponent`, with
+/// trailing zeros moved from `digits` into `exponent`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Decimal {
+    pub negative: bool,
+    pub digits: String,
+    pub exponent: i64,
+}
+
+impl Decimal {
+    /// Parses a JSON number token such as `-12.50e3` without going through a
+    /// float, so no precision is lost.
+    pub fn parse(token: &str) -> Option<Decimal> {
+        let (negative, unsigned) = match token.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+
+        let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+            Some(index) => (
+                &unsigned[..index],
+                unsigned[index + 1..].parse::<i64>().ok()?,
+            ),
+            None => (unsigned, 0),
+        };
+
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if integer.is_empty()
+            || !(integer.bytes().chain(fraction.bytes())).all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+
+        let mut digits = format!("{}{}", integer, fraction);
+        let mut exponent = exponent.checked_sub(fraction.len() as i64)?;
+
+        let trimmed = digits.trim_end_matches('0').len();
+        exponent = exponent.checked_add((digits.len() - trimmed) as i64)?;
+        digits.truncate(trimmed);
+
+        let digits = digits.trim_start_matches('0').to_string();
+        if digits.is_empty() {
+            return Some(Decimal {
+                negative: false,
+                digits: "0".to_string(),
+                exponent: 0,
+            });
+        }
+
+        Some(Decimal {
+            negative,
+            digits,
+            exponent,
+        })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits == "0"
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.exponent >= 0
+    }
+
+    /// Converts to an integer, or `None` if it isn't one or would be longer
+    /// than `max_digits`.
+    pub fn to_integer(&self, max_digits: usize) -> Option<BigInt> {
+        if !self.is_integer() {
+            return None;
+        }
+
+        let length = (self.digits.len() as i64).checked_add(self.exponent)?;
+        if length > max_digits as i64 {
+            return None;
+        }
+
+        let digits = format!("{}{}", self.digits, "0".repeat(self.exponent as usize));
+        let magnitude = digits.parse::<BigUint>().ok()?;
+        let sign = if self.negative {
+            Sign::Minus
+        } else {
+            Sign::Plus
+        };
+
+        Some(BigInt::from_biguint(sign, magnitude))
+    }
+}
+
+/// The digits of the positive integer in a raw JSON number token that still
+/// needs a primality test, or `None` if it can't be prime. Negative numbers
+/// and non-integers are never prime.
+pub fn candidate_digits(token: &str) -> Option<String> {
+    let decimal = Decimal::parse(token)?;
+
+    // a positive exponent left after trimming zeros is a multiple of ten
+    if decimal.negative || decimal.is_zero() || decimal.exponent != 0 {
+        return None;
+    }
+
+    Some(decimal.digits)
+}
+
+pub fn is_prime_digits(digits: &str) -> bool {
+    match digits.parse::<BigUint>() {
+        Ok(n) => is_prime(&n),
+        Err(_) => false,
+    }
+}
+
+/// Smallest prime strictly greater than `n`.
+pub fn next_prime(n: &BigInt) -> BigUint {
+    let mut candidate = match n.to_biguint() {
+        Some(n) if n >= BigUint::from(2u32) => n + 1u32,
+        _ => return BigUint::from(2u32),
+    };
+
+    if (&candidate % 2u32).is_zero() {
+        if candidate == BigUint::from(2u32) {
+            return candidate;
+        }
+        candidate += 1u32;
+    }
+
+    while !is_prime(&candidate) {
+        candidate += 2u32;
+    }
+
+    candidate
+}
+
+/// Largest prime strictly less than `n`, if there is one.
+pub fn prev_prime(n: &BigInt) -> Option<BigUint> {
+    let n = n.to_biguint()?;
+    if n <= BigUint::from(2u32) {
+        return None;
+    }
+    if n == BigUint::from(3u32) {
+        return Some(BigUint::from(2u32));
+    }
+
+    let mut candidate = n - 1u32;
+    if (&candidate % 2u32).is_zero() {
+        candidate -= 1u32;
+    }
+
+    while !is_prime(&candidate) {
+        candidate -= 2u32;
+    }
+
+    Some(candidate)
+}
+
+/// Prime factors of `n` in ascending order, with multiplicity.
+pub fn factorize(n: u64) -> Vec<u64> {
+    let mut factors = vec![];
+    let mut n = n;
+
+    for p in U64_BASES {
+        while n > 1 && n.is_multiple_of(p) {
+            factors.push(p);
+            n /= p;
+        }
+    }
+
+    let mut remaining = vec![n];
+    while let Some(m) = remaining.pop() {
+        if m <= 1 {
+            continue;
+        }
+
+        if is_prime_u64(m) {
+            factors.push(m);
+        } else {
+            let d = pollard_rho(m);
+            remaining.push(d);
+            remaining.push(m / d);
+        }
+    }
+
+    factors.sort_unstable();
+    factors
+}
+
+/// Number of primes less than or equal to `n`, counted with a segmented
+/// sieve of Eratosthenes.
+pub fn prime_count(n: u64) -> u64 {
+    const SEGMENT_SIZE: u64 = 1 << 18;
+
+    if n < 2 {
+        return 0;
+    }
+
+    let small_primes = primes_up_to(n.isqrt());
+    let mut segment = vec![true; SEGMENT_SIZE as usize];
+    let mut count = 0;
+    let mut low = 2;
+
+    while low <= n {
+        let high = n.min(low + SEGMENT_SIZE - 1);
+        let segment = &mut segment[..(high - low + 1) as usize];
+        segment.fill(true);
+
+        for &p in &small_primes {
+            if p * p > high {
+                break;
+            }
+
+            let start = (p * p).max(low.div_ceil(p) * p);
+            for multiple in (start..=high).step_by(p as usize) {
+                segment[(multiple - low) as usize] = false;
+            }
+        }
+
+        count += segment.iter().filter(|&&is_prime| is_prime).count() as u64;
+        low = high + 1;
+    }
+
+    count
+}
+
+/// All primes up to and including `limit`.
+pub fn primes_up_to(limit: u64) -> Vec<u64> {
+    if limit < 2 {
+        return vec![];
+    }
+
+    let mut sieve = vec![true; limit as usize + 1];
+    sieve[0] = false;
+    sieve[1] = false;
+
+    let mut i = 2;
+    while i * i <= limit as usize {
+        if sieve[i] {
+            for multiple in (i * i..=limit as usize).step_by(i) {
+                sieve[multiple] = false;
+            }
+        }
+        i += 1;
+    }
+
+    sieve
+        .iter()
+        .enumerate()
+        .filter(|(_, &is_prime)| is_prime)
+        .map(|(n, _)| n as u64)
+        .collect()
+}
+
+/// Tests `u64`s with deterministic Miller-Rabin and anything larger with
+/// Baillie-PSW: a strong probable prime test to base 2 and a strong Lucas
+/// test. Baillie-PSW isn't proven past `u64`, but no composite is known to
+/// pass it.
+pub fn is_prime(n: &BigUint) -> bool {
+    if let Some(n) = n.to_u64() {
+        return is_prime_u64(n);
+    }
+
+    for p in SMALL_PRIMES {
+        if (n % p).is_zero() {
+            return false;
+        }
+    }
+
+    is_strong_probable_prime(n, 2) && is_strong_lucas_probable_prime(n)
+}
+
+/// Miller-Rabin with a single base, for odd `n` above 2.
+fn is_strong_probable_prime(n: &BigUint, base: u32) -> bool {
+    let one = BigUint::one();
+    let n_minus_one = n - &one;
+    let s = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> s;
+
+    let mut x = BigUint::from(base).modpow(&d, n);
+    if x == one || x == n_minus_one {
+        return true;
+    }
+
+    for _ in 1..s {
+        x = &x * &x % n;
+        if x == n_minus_one {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// The strong Lucas test with Selfridge's parameters: `D` is the first of 5,
+/// -7, 9, -11, ... with Jacobi symbol `(D/n) = -1`, `P = 1` and
+/// `Q = (1 - D) / 4`. `n` must be odd.
+fn is_strong_lucas_probable_prime(n: &BigUint) -> bool {
+    // no such `D` exists for a perfect square
+    let root = n.sqrt();
+    if &root * &root == *n {
+        return false;
+    }
+
+    let mut d: i64 = 5;
+    loop {
+        match jacobi(d, n) {
+            -1 => break,
+            // `D` shares a factor with `n`
+            0 if n != &BigUint::from(d.unsigned_abs()) => return false,
+            _ => {}
+        }
+        d = if d > 0 { -(d + 2) } else { -d + 2 };
+    }
+
+    let modulus = BigInt::from(n.clone());
+    let reduce = |x: BigInt| {
+        let x = x % &modulus;
+        if x.sign() == Sign::Minus {
+            x + &modulus
+        } else {
+            x
+        }
+    };
+    // `n` is odd, so an odd residue becomes even by adding it
+    let halve = |x: BigInt| {
+        let x = if x.bit(0) { x + &modulus } else { x };
+        reduce(x >> 1)
+    };
+
+    let (p, q) = (BigInt::one(), BigInt::from((1 - d) / 4));
+    let d = BigInt::from(d);
+
+    let n_plus_one = n + 1u32;
+    let s = n_plus_one.trailing_zeros().unwrap_or(0);
+    let k = &n_plus_one >> s;
+
+    // U_k, V_k and Q^k, doubling along the bits of `k` from the top
+    let (mut u, mut v, mut q_k) = (BigInt::one(), p.clone(), reduce(q.clone()));
+    for bit in (0..k.bits() - 1).rev() {
+        u = reduce(&u * &v);
+        v = reduce(&v * &v - &q_k * 2);
+        q_k = reduce(&q_k * &q_k);
+
+        if k.bit(bit) {
+            (u, v) = (halve(&p * &u + &v), halve(&d * &u + &p * &v));
+            q_k = reduce(&q_k * &q);
+        }
+    }
+
+    if u.is_zero() || v.is_zero() {
+        return true;
+    }
+
+    for _ in 1..s {
+        v = reduce(&v * &v - &q_k * 2);
+        if v.is_zero() {
+            return true;
+        }
+        q_k = reduce(&q_k * &q_k);
+    }
+
+    false
+}
+
+/// The Jacobi symbol `(a/n)` for odd `n`.
+fn jacobi(a: i64, n: &BigUint) -> i32 {
+    let mut a = match a < 0 {
+        true => n - BigUint::from(a.unsigned_abs()) % n,
+        false => BigUint::from(a as u64) % n,
+    };
+    let mut n = n.clone();
+    let mut result = 1;
+
+    while !a.is_zero() {
+        while !a.bit(0) {
+            a >>= 1;
+            if matches!((&n % 8u32).to_u32(), Some(3 | 5)) {
+                result = -result;
+            }
+        }
+
+        std::mem::swap(&mut a, &mut n);
+        if (&a % 4u32).to_u32() == Some(3) && (&n % 4u32).to_u32() == Some(3) {
+            result = -result;
+        }
+        a %= &n;
+    }
+
+    match n.is_one() {
+        true => result,
+        false => 0,
+    }
+}
+
+pub fn is_prime_u64(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+
+    for p in U64_BASES {
+        if n.is_multiple_of(p) {
+            return n == p;
+        }
+    }
+
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+
+    U64_BASES.iter().all(|&a| {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                return true;
+            }
+        }
+
+        false
+    })
+}
+
+/// Finds a non-trivial factor of the odd composite `n`.
+fn pollard_rho(n: u64) -> u64 {
+    for c in 1.. {
+        let f = |x: u64| ((x as u128 * x as u128 + c as u128) % n as u128) as u64;
+        let (mut x, mut y, mut d) = (2, 2, 1);
+
+        while d == 1 {
+            x = f(x);
+            y = f(f(y));
+            d = gcd(x.abs_diff(y), n);
+        }
+
+        if d != n {
+            return d;
+        }
+    }
+
+    unreachable!()
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 * b as u128) % m as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exponent: u64, m: u64) -> u64 {
+    let mut result = 1;
+    base %= m;
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exponent >>= 1;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_decimal() {
+        assert_eq!(
+            Decimal::parse("-12.50e3"),
+            Some(Decimal {
+                negative: true,
+                digits: "125".to_string(),
+                exponent: 2,
+            })
+        );
+        assert_eq!(
+            Decimal::parse("0.0"),
+            Some(Decimal {
+                negative: false,
+                digits: "0".to_string(),
+                exponent: 0,
+            })
+        );
+        assert_eq!(Decimal::parse("abc"), None);
+        assert_eq!(Decimal::parse(".5"), None);
+    }
+
+    #[test]
+    fn test_is_prime_token() {
+        let cases = [
+            ("-7", false),
+            ("-1", false),
+            ("0", false),
+            ("-0", false),
+            ("1", false),
+            ("2", true),
+            ("3", true),
+            ("4", false),
+            ("561", false),
+            ("7.0", true),
+            ("7.5", false),
+            ("0.7e1", true),
+            ("2.5", false),
+            ("1e1", false),
+            ("7e0", true),
+            ("7E+0", true),
+            ("1e-999999999999", false),
+            ("1e999999999999", false),
+            // around 2^53, where f64 stops representing every integer
+            ("9007199254740991", false),
+            ("9007199254740993", false),
+            ("9007199254740997", true),
+            // largest prime below 2^64 and the first u64 overflow
+            ("18446744073709551557", true),
+            ("18446744073709551616", false),
+            ("2305843009213693951", true),
+            ("618970019642690137449562111", true),
+            ("618970019642690137449562113", false),
+            ("170141183460469231731687303715884105727", true),
+            ("170141183460469231731687303715884105729", false),
+        ];
+
+        for (token, expected) in cases {
+            let prime = candidate_digits(token).is_some_and(|d| is_prime_digits(&d));
+            assert_eq!(prime, expected, "{}", token);
+        }
+    }
+
+    #[test]
+    fn test_next_and_prev_prime() {
+        let next = |n: i64| next_prime(&BigInt::from(n)).to_string();
+        let prev = |n: i64| prev_prime(&BigInt::from(n)).map(|p| p.to_string());
+
+        assert_eq!(next(-5), "2");
+        assert_eq!(next(2), "3");
+        assert_eq!(next(13), "17");
+        assert_eq!(prev(2), None);
+        assert_eq!(prev(3), Some("2".to_string()));
+        assert_eq!(prev(17), Some("13".to_string()));
+
+        let big = BigInt::from(u64::MAX);
+        assert_eq!(next_prime(&big).to_string(), "18446744073709551629");
+        assert_eq!(
+            prev_prime(&big).map(|p| p.to_string()),
+            Some("18446744073709551557".to_string())
+        );
+    }
+
+    #[test]
+    fn test_factorize() {
+        assert_eq!(factorize(1), Vec::<u64>::new());
+        assert_eq!(factorize(2), vec![2]);
+        assert_eq!(factorize(360), vec![2, 2, 2, 3, 3, 5]);
+        assert_eq!(factorize(9007199254740991), vec![6361, 69431, 20394401]);
+        assert_eq!(factorize(18446744073709551557), vec![18446744073709551557]);
+        assert_eq!(
+            factorize(4294967291 * 4294967279),
+            vec![4294967279, 4294967291]
+        );
+    }
+
+    #[test]
+    fn test_prime_count() {
+        assert_eq!(prime_count(0), 0);
+        assert_eq!(prime_count(2), 1);
+        assert_eq!(prime_count(100), 25);
+        assert_eq!(prime_count(1_000_000), 78498);
+    }
+
+    #[test]
+    fn test_is_prime_u64_matches_trial_division() {
+        let trial = |n: u64| {
+            n >= 2
+                && (2..)
+                    .take_while(|d| d * d <= n)
+                    .all(|d| !n.is_multiple_of(d))
+        };
+
+        for n in 0..10_000 {
+            assert_eq!(is_prime_u64(n), trial(n), "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_baillie_psw_parts() {
+        // strong pseudoprimes to base 2, which the Lucas test catches
+        for n in [
+            2047u64,
+            3277,
+            4033,
+            4681,
+            8321,
+            3215031751,
+            3825123056546413051,
+        ] {
+            let n = BigUint::from(n);
+            assert!(is_strong_probable_prime(&n, 2), "{}", n);
+            assert!(!is_strong_lucas_probable_prime(&n), "{}", n);
+        }
+        // strong Lucas pseudoprimes, which base 2 catches
+        for n in [5459u64, 5777, 10877, 16109, 18971, 22499, 24569, 25199] {
+            let n = BigUint::from(n);
+            assert!(is_strong_lucas_probable_prime(&n), "{}", n);
+            assert!(!is_strong_probable_prime(&n, 2), "{}", n);
+        }
+
+        // together they're exact below 2^64
+        let mut rng = crate::util::XorShift(0x9E37_79B9_7F4A_7C15);
+        let odd = (3..20_000u64)
+            .step_by(2)
+            .chain((0..2000).map(|_| rng.next() | 1));
+        for n in odd {
+            let big = BigUint::from(n);
+            let bpsw = is_strong_probable_prime(&big, 2) && is_strong_lucas_probable_prime(&big);
+            assert_eq!(bpsw, is_prime_u64(n), "{}", n);
+        }
+
+        // past 2^64: a Mersenne prime and a product of two
+        let m61 = BigUint::from((1u64 << 61) - 1);
+        let m89 = (BigUint::one() << 89usize) - 1u32;
+        assert!(is_prime(&m89));
+        assert!(!is_prime(&(&m61 * &m89)));
+        assert!(!is_prime(&(&m89 * &m89)));
+    }
+}
@@ -0,0 +1,134 @@
+use super::prime::primes_up_to;
+
+/// Odd numbers marked per segment while building, sized to stay in cache.
+const SEGMENT_SIZE: u64 = 1 << 21;
+
+/// Primality of every number up to `limit`, precomputed with a segmented
+/// sieve of Eratosthenes. Only odd numbers are stored: bit `i` is set when
+/// `2i + 1` is prime.
+pub struct Sieve {
+    limit: u64,
+    bits: Vec<u64>,
+}
+
+impl Sieve {
+    pub fn new(limit: u64) -> Sieve {
+        let odd_count = limit.div_ceil(2);
+        let mut bits = vec![!0u64; odd_count.div_ceil(64) as usize];
+
+        // 1 isn't prime, and clear the padding past `limit`
+        if let Some(first) = bits.first_mut() {
+            *first &= !1;
+        }
+        if !odd_count.is_multiple_of(64) {
+            if let Some(last) = bits.last_mut() {
+                *last &= (1 << (odd_count % 64)) - 1;
+            }
+        }
+
+        let primes: Vec<u64> = primes_up_to(limit.isqrt())
+            .into_iter()
+            .filter(|&p| p > 2)
+            .collect();
+        // index of the next odd multiple to cross off for each prime, starting
+        // at its square
+        let mut next: Vec<u64> = primes.iter().map(|&p| p * p / 2).collect();
+
+        let mut low = 0;
+        while low < odd_count {
+            let high = odd_count.min(low + SEGMENT_SIZE);
+
+            for (&p, next) in primes.iter().zip(next.iter_mut()) {
+                let mut i = *next;
+                while i < high {
+                    bits[(i / 64) as usize] &= !(1 << (i % 64));
+                    i += p;
+                }
+                *next = i;
+            }
+
+            low = high;
+        }
+
+        Sieve { limit, bits }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.bits.len() * std::mem::size_of::<u64>()
+    }
+
+    /// Whether `n` is prime, or `None` if it's beyond the sieve.
+    pub fn is_prime(&self, n: u64) -> Option<bool> {
+        if n > self.limit {
+            return None;
+        }
+
+        Some(match n {
+            2 => true,
+            _ if n.is_multiple_of(2) => false,
+            _ => self.bit(n / 2),
+        })
+    }
+
+    /// Number of primes up to and including `n`, or `None` if it's beyond
+    /// the sieve.
+    pub fn prime_count(&self, n: u64) -> Option<u64> {
+        if n > self.limit {
+            return None;
+        }
+        if n < 2 {
+            return Some(0);
+        }
+
+        // odd numbers up to `n` are indices 0..=last, plus the even prime 2
+        let last = (n - 1) / 2;
+        let full_words = ((last + 1) / 64) as usize;
+
+        let mut count: u64 = self.bits[..full_words]
+            .iter()
+            .map(|w| w.count_ones() as u64)
+            .sum();
+        let remainder = (last + 1) % 64;
+        if remainder > 0 {
+            count += (self.bits[full_words] & ((1 << remainder) - 1)).count_ones() as u64;
+        }
+
+        Some(count + 1)
+    }
+
+    fn bit(&self, i: u64) -> bool {
+        self.bits[(i / 64) as usize] & (1 << (i % 64)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::prime::{is_prime_u64, prime_count};
+    use super::*;
+
+    #[test]
+    fn test_sieve_matches_miller_rabin() {
+        for limit in [0, 1, 2, 3, 64, 127, 128, 129, 100_000] {
+            let sieve = Sieve::new(limit);
+
+            for n in 0..=limit + 2 {
+                let expected = (n <= limit).then(|| is_prime_u64(n));
+                assert_eq!(sieve.is_prime(n), expected, "{} in sieve to {}", n, limit);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sieve_prime_count() {
+        let sieve = Sieve::new(1_000_000);
+
+        for n in [0, 1, 2, 3, 10, 127, 128, 129, 1000, 999_983, 1_000_000] {
+            assert_eq!(sieve.prime_count(n), Some(prime_count(n)), "{}", n);
+        }
+        assert_eq!(sieve.prime_count(1_000_001), None);
+    }
+}
@@ -0,0 +1,193 @@
+use serde_json::{Map, Number, Value};
+
+use super::methods::Methods;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Methods,
+    /// Kept as the raw JSON token so big integers and fractions are handled
+    /// exactly rather than through an `f64`.
+    pub number: Number,
+    /// Fields besides `method` and `number`, which the spec says to ignore.
+    pub extra_fields: Vec<String>,
+}
+
+/// Why a request line was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Malformed {
+    InvalidJson,
+    /// A binary frame that couldn't be decoded or was too large.
+    InvalidFrame(String),
+    UnknownEncoding(String),
+    NotAnObject,
+    MissingField(&'static str),
+    MethodNotAString,
+    UnknownMethod(String),
+    NotANumber,
+    InvalidParams(String),
+    Internal(String),
+}
+
+impl std::fmt::Display for Malformed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Malformed::InvalidJson => write!(f, "request is not valid JSON"),
+            Malformed::InvalidFrame(message) => write!(f, "invalid frame: {}", message),
+            Malformed::UnknownEncoding(name) => write!(f, "unknown encoding {:?}", name),
+            Malformed::NotAnObject => write!(f, "request is not a JSON object"),
+            Malformed::MissingField(field) => write!(f, "missing field {:?}", field),
+            Malformed::MethodNotAString => write!(f, "method is not a string"),
+            Malformed::UnknownMethod(method) => write!(f, "unknown method {:?}", method),
+            Malformed::NotANumber => write!(f, "number is not a JSON number"),
+            Malformed::InvalidParams(message) => write!(f, "{}", message),
+            Malformed::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Malformed {}
+
+/// Validates one request line against the Protohackers spec: it must be a
+/// JSON object with a known `method` name and a `number` that is a JSON
+/// number. Anything else, including numbers sent as strings, is malformed.
+pub fn parse_request(line: &[u8]) -> Result<Request, Malformed> {
+    match serde_json::from_slice::<Value>(line) {
+        Ok(request) => validate(request),
+        Err(_) => Err(Malformed::InvalidJson),
+    }
+}
+
+/// Validates an already decoded request.
+pub fn validate(request: Value) -> Result<Request, Malformed> {
+    let request = match request {
+        Value::Object(request) => request,
+        _ => return Err(Malformed::NotAnObject),
+    };
+
+    let method = match field(&request, "method")? {
+        Value::String(method) => serde_json::from_value::<Methods>(Value::String(method.clone()))
+            .map_err(|_| Malformed::UnknownMethod(method.clone()))?,
+        _ => return Err(Malformed::MethodNotAString),
+    };
+
+    let number = match field(&request, "number")? {
+        Value::Number(number) => number.clone(),
+        _ => return Err(Malformed::NotANumber),
+    };
+
+    let extra_fields = request
+        .keys()
+        .filter(|key| *key != "method" && *key != "number")
+        .cloned()
+        .collect();
+
+    Ok(Request {
+        method,
+        number,
+        extra_fields,
+    })
+}
+
+fn field<'a>(request: &'a Map<String, Value>, name: &'static str) -> Result<&'a Value, Malformed> {
+    request.get(name).ok_or(Malformed::MissingField(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conformance() {
+        let valid = |method, number: &str, extra_fields: &[&str]| {
+            Ok(Request {
+                method,
+                number: serde_json::from_str(number).unwrap(),
+                extra_fields: extra_fields.iter().map(|f| f.to_string()).collect(),
+            })
+        };
+
+        let cases = [
+            (
+                r#"{"method":"isPrime","number":123}"#,
+                valid(Methods::IsPrime, "123", &[]),
+            ),
+            (
+                r#"{"number":-4.5,"method":"isPrime"}"#,
+                valid(Methods::IsPrime, "-4.5", &[]),
+            ),
+            (
+                r#"{"method":"isPrime","number":1e400}"#,
+                valid(Methods::IsPrime, "1e400", &[]),
+            ),
+            // extraneous fields are ignored
+            (
+                r#"{"method":"isPrime","number":7,"id":null,"x":[1]}"#,
+                valid(Methods::IsPrime, "7", &["id", "x"]),
+            ),
+            (
+                r#"{"method":"factorize","number":12}"#,
+                valid(Methods::Factorize, "12", &[]),
+            ),
+            ("", Err(Malformed::InvalidJson)),
+            ("{", Err(Malformed::InvalidJson)),
+            ("isPrime 7", Err(Malformed::InvalidJson)),
+            (
+                r#"{"method":"isPrime","number":7}}"#,
+                Err(Malformed::InvalidJson),
+            ),
+            (
+                r#"{"method":"isPrime","number":NaN}"#,
+                Err(Malformed::InvalidJson),
+            ),
+            ("[]", Err(Malformed::NotAnObject)),
+            ("7", Err(Malformed::NotAnObject)),
+            ("null", Err(Malformed::NotAnObject)),
+            ("{}", Err(Malformed::MissingField("method"))),
+            (r#"{"number":7}"#, Err(Malformed::MissingField("method"))),
+            (
+                r#"{"method":"isPrime"}"#,
+                Err(Malformed::MissingField("number")),
+            ),
+            (
+                r#"{"method":null,"number":7}"#,
+                Err(Malformed::MethodNotAString),
+            ),
+            (
+                r#"{"method":["isPrime"],"number":7}"#,
+                Err(Malformed::MethodNotAString),
+            ),
+            (
+                r#"{"method":"isprime","number":7}"#,
+                Err(Malformed::UnknownMethod("isprime".to_string())),
+            ),
+            (
+                r#"{"method":"","number":7}"#,
+                Err(Malformed::UnknownMethod("".to_string())),
+            ),
+            (
+                r#"{"method":"isPrime","number":"7"}"#,
+                Err(Malformed::NotANumber),
+            ),
+            (
+                r#"{"method":"isPrime","number":null}"#,
+                Err(Malformed::NotANumber),
+            ),
+            (
+                r#"{"method":"isPrime","number":true}"#,
+                Err(Malformed::NotANumber),
+            ),
+            (
+                r#"{"method":"isPrime","number":[7]}"#,
+                Err(Malformed::NotANumber),
+            ),
+            (
+                r#"{"method":"isPrime","number":{}}"#,
+                Err(Malformed::NotANumber),
+            ),
+        ];
+
+        for (line, expected) in cases {
+            assert_eq!(parse_request(line.as_bytes()), expected, "{}", line);
+        }
+    }
+}
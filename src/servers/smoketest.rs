@@ -31,7 +31,7 @@ async fn handle_connection(mut socket: TcpStream, addr: SocketAddr) {
 
     loop {
         let bytes_read = match socket.read(&mut bytes).await {
-            Ok(n) if n == 0 => return,
+            Ok(0) => return,
             Ok(n) => n,
             Err(e) => {
                 eprintln!("Failed to read from socket: {}", e);
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Rough per-entry bookkeeping cost on top of the key and value.
+const ENTRY_OVERHEAD: usize = 64;
+
+struct Entry {
+    value: Vec<u8>,
+    /// Unix time in milliseconds the entry expires at.
+    expires_at: Option<u64>,
+    used: u64,
+}
+
+/// Key-value entries with optional expiry times and an optional limit on
+/// the number of keys, evicting the least recently used beyond it. Recency is
+/// only tracked with a limit, and expiry times only when `expires` is set, so
+/// the plain store pays for neither.
+pub struct Entries {
+    max_keys: Option<usize>,
+    expires: bool,
+    bytes: usize,
+    tick: u64,
+    map: HashMap<Vec<u8>, Entry>,
+    /// Keys by the tick they were last used at, oldest first.
+    recency: BTreeMap<u64, Vec<u8>>,
+    /// Keys by expiry time, soonest first.
+    expiry: BTreeSet<(u64, Vec<u8>)>,
+}
+
+impl Entries {
+    pub fn new(max_keys: Option<usize>, expires: bool) -> Entries {
+        Entries {
+            max_keys,
+            expires,
+            bytes: 0,
+            tick: 0,
+            map: HashMap::new(),
+            recency: BTreeMap::new(),
+            expiry: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the value unless it's missing or expired at `now`, marking it
+    /// as used.
+    pub fn get(&mut self, key: &[u8], now: u64) -> Option<&[u8]> {
+        let entry = self.map.get_mut(key)?;
+        if entry.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return None;
+        }
+
+        if self.max_keys.is_some() {
+            self.tick += 1;
+            let key = self.recency.remove(&entry.used)?;
+            entry.used = self.tick;
+            self.recency.insert(self.tick, key);
+        }
+
+        Some(&entry.value)
+    }
+
+    /// Stores the value, returning the keys evicted to make room for it. The
+    /// expiry time is dropped unless entries expire.
+    pub fn insert(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+    ) -> Vec<Vec<u8>> {
+        self.remove(&key);
+
+        let expires_at = expires_at.filter(|_| self.expires);
+        self.bytes += self.cost(&key, &value, expires_at);
+        if let Some(expires_at) = expires_at {
+            self.expiry.insert((expires_at, key.clone()));
+        }
+        if self.max_keys.is_some() {
+            self.tick += 1;
+            self.recency.insert(self.tick, key.clone());
+        }
+        self.map.insert(
+            key,
+            Entry {
+                value,
+                expires_at,
+                used: self.tick,
+            },
+        );
+
+        let mut evicted = vec![];
+        while self
+            .max_keys
+            .is_some_and(|max_keys| self.map.len() > max_keys)
+        {
+            let oldest = match self.recency.first_key_value() {
+                Some((_, oldest)) => oldest.clone(),
+                None => break,
+            };
+            self.remove(&oldest);
+            evicted.push(oldest);
+        }
+
+        evicted
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        let entry = match self.map.remove(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        self.bytes -= self.cost(key, &entry.value, entry.expires_at);
+        if self.max_keys.is_some() {
+            self.recency.remove(&entry.used);
+        }
+        if let Some(expires_at) = entry.expires_at {
+            self.expiry.remove(&(expires_at, key.to_vec()));
+        }
+
+        true
+    }
+
+    /// Removes every entry expired at `now`, returning their keys.
+    pub fn remove_expired(&mut self, now: u64) -> Vec<Vec<u8>> {
+        let mut expired = vec![];
+
+        while let Some((expires_at, key)) = self.expiry.first() {
+            if *expires_at > now {
+                break;
+            }
+            let key = key.clone();
+            self.remove(&key);
+            expired.push(key);
+        }
+
+        expired
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8], Option<u64>)> {
+        self.map
+            .iter()
+            .map(|(key, entry)| (key.as_slice(), entry.value.as_slice(), entry.expires_at))
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Estimate of the memory used by the entries.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn cost(&self, key: &[u8], value: &[u8], expires_at: Option<u64>) -> usize {
+        // the key is stored in the map and in whichever indexes track it
+        let copies = 1 + self.max_keys.is_some() as usize + expires_at.is_some() as usize;
+        key.len() * copies + value.len() + ENTRY_OVERHEAD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiry_and_eviction() {
+        let mut entries = Entries::new(Some(2), true);
+
+        assert!(entries
+            .insert(b"a".to_vec(), b"1".to_vec(), Some(100))
+            .is_empty());
+        assert!(entries
+            .insert(b"b".to_vec(), b"2".to_vec(), None)
+            .is_empty());
+        assert_eq!(entries.get(b"a", 99), Some(&b"1"[..]));
+        assert_eq!(entries.get(b"a", 100), None);
+
+        // `b` is the least recently used now
+        assert_eq!(
+            entries.insert(b"c".to_vec(), b"3".to_vec(), Some(50)),
+            vec![b"b".to_vec()]
+        );
+        assert_eq!(entries.get(b"b", 0), None);
+
+        assert_eq!(entries.remove_expired(60), vec![b"c".to_vec()]);
+        assert_eq!(entries.remove_expired(60), Vec::<Vec<u8>>::new());
+        assert_eq!(entries.len(), 1);
+
+        // replacing a key drops its old expiry
+        entries.insert(b"a".to_vec(), b"4".to_vec(), None);
+        assert!(entries.remove_expired(1000).is_empty());
+        assert_eq!(entries.get(b"a", 1000), Some(&b"4"[..]));
+        assert_eq!(entries.bytes(), entries.cost(b"a", b"4", None));
+
+        entries.remove(b"a");
+        assert_eq!((entries.len(), entries.bytes()), (0, 0));
+    }
+
+    #[test]
+    fn test_untracked_entries() {
+        let mut entries = Entries::new(None, false);
+
+        for i in 0..100u64 {
+            entries.insert(i.to_be_bytes().to_vec(), vec![], Some(i));
+        }
+        assert_eq!(entries.get(&0u64.to_be_bytes(), 1000), Some(&b""[..]));
+        assert!(entries.remove_expired(1000).is_empty());
+        assert_eq!(entries.len(), 100);
+        assert!(entries.recency.is_empty() && entries.expiry.is_empty());
+        assert_eq!(entries.tick, 0);
+
+        for i in 0..100u64 {
+            entries.remove(&i.to_be_bytes());
+        }
+        assert_eq!(entries.bytes(), 0);
+    }
+}
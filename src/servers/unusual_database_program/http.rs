@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use crate::{
+    http::{self, Request, Response},
+    util::Result,
+};
+
+use super::{Inserted, Mode, Shared, MAX_DATAGRAM, MAX_TTL, PREFIX};
+
+const KEYS_PATH: &str = "/keys/";
+
+/// Serves `GET /keys/<key>` and `PUT /keys/<key>` with the value as the body.
+/// In extended mode `PUT` takes a `ttl` in seconds as a query parameter. On a
+/// replica it's rejected with a `403`, or accepted with a `202` once it's been
+/// queued for the primary. That's best effort, since the connection may drop
+/// before it's sent, and a `503` means it couldn't even be queued.
+pub async fn serve(listener: TcpListener, db: Shared) -> Result<()> {
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let db = db.clone();
+
+        tokio::spawn(async move {
+            let handler = |request| handle_request(request, db.clone());
+            if let Err(e) = http::serve_connection(socket, handler).await {
+                eprintln!("[{}] HTTP error occurred with {}: {}", PREFIX, addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_request(request: Request, db: Shared) -> Response {
+    println!("[{}] HTTP {} {}", PREFIX, request.method, request.path);
+
+    // keys are raw bytes, so they're decoded from the path as sent
+    let key = match request.raw_path.strip_prefix(KEYS_PATH) {
+        Some(key) => http::percent_decode_bytes(key),
+        None => return Response::text(404, "not found"),
+    };
+    // UDP and TCP clients couldn't name such a key
+    if key.contains(&b'=') || key.contains(&b'\n') {
+        return Response::text(400, "key can't contain = or a newline");
+    }
+
+    let mut db = db.lock().await;
+    db.requests += 1;
+
+    match request.method.as_str() {
+        "GET" => match db.get(&key) {
+            Some(value) => Response::new(200, "application/octet-stream", value),
+            None => Response::text(404, "no such key"),
+        },
+        "PUT" => {
+            // the same limit as the `key=value` datagram it stands in for
+            if key.len() + 1 + request.body.len() > MAX_DATAGRAM {
+                return Response::text(413, "key and value must be under 1000 bytes");
+            }
+            let ttl = match (db.mode, request.query_param("ttl")) {
+                (Mode::Extended, Some(ttl)) => match ttl.parse() {
+                    Ok(seconds) if seconds <= MAX_TTL.as_secs() => {
+                        Some(Duration::from_secs(seconds))
+                    }
+                    _ => {
+                        let message = format!(
+                            "ttl must be a number of seconds up to {}",
+                            MAX_TTL.as_secs()
+                        );
+                        return Response::text(400, &message);
+                    }
+                },
+                _ => None,
+            };
+
+            match db
+                .insert(&key, &request.body, ttl)
+                .await
+                .map_err(|e| e.to_string())
+            {
+                Ok(Inserted::Stored) => Response::new(204, "text/plain; charset=utf-8", vec![]),
+                Ok(Inserted::Forwarded) => Response::text(202, "forwarded to the primary"),
+                Ok(Inserted::Reserved) => Response::text(403, "key is reserved"),
+                Ok(Inserted::Rejected) => Response::text(403, "replica is read only"),
+                Ok(Inserted::Unavailable) => Response::text(503, "primary is unavailable"),
+                Err(e) => {
+                    eprintln!("Failed to store value: {}", e);
+                    Response::text(500, "failed to store value")
+                }
+            }
+        }
+        _ => Response::text(405, "method not allowed").with_header("Allow", "GET, PUT"),
+    }
+}
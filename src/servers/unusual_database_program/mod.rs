@@ -0,0 +1,1217 @@
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::util::{env_var, Result};
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    sync::{broadcast, Mutex},
+};
+
+use self::{
+    entries::Entries,
+    replication::{Change, ReplicaWrites, Write, Writes},
+    reserved::{Registry, Stats},
+    wal::{Fsync, Wal, WalConfig},
+};
+
+mod entries;
+mod http;
+mod replication;
+mod reserved;
+mod tcp;
+mod wal;
+
+const PREFIX: &str = "UDP";
+
+/// Requests and responses must be shorter than 1000 bytes.
+const MAX_DATAGRAM: usize = 999;
+
+/// Longest expiry, a century, so expiry times can't overflow.
+const MAX_TTL: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// The store as specified by Protohackers.
+    #[default]
+    Strict,
+    /// Adds key expiry, a key limit and the `uptime`, `keys`, `memory` and
+    /// `requests` introspection keys.
+    Extended,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mode: Mode,
+    /// Directory inserts are logged to so they survive restarts.
+    pub wal: Option<WalConfig>,
+    pub oversize: Oversize,
+    /// Extended mode only: keys kept before evicting the least recently used.
+    pub max_keys: Option<usize>,
+    /// Extended mode only: expiry for inserts that don't set their own.
+    pub default_ttl: Option<Duration>,
+    /// How often expired keys are swept in extended mode.
+    pub sweep_interval: Duration,
+    /// Read-only keys with fixed values, overriding computed ones.
+    pub reserved: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Serves the same store as newline delimited requests over TCP.
+    pub tcp_port: Option<String>,
+    /// Serves the same store as `GET` and `PUT /keys/<key>` over HTTP.
+    pub http_port: Option<String>,
+    /// Streams the store to replicas connecting on this port.
+    pub replication_port: Option<String>,
+    /// Address of a primary to replicate, making this a replica.
+    pub primary: Option<String>,
+    pub replica_writes: ReplicaWrites,
+    /// Shared by a primary and its replicas, which it turns away without it.
+    pub replication_secret: Option<String>,
+}
+
+/// What to do with a request or response of 1000 bytes or more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Oversize {
+    /// Drop it, as the spec allows.
+    #[default]
+    Ignore,
+    /// Keep its first 999 bytes.
+    Truncate,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            mode: Mode::Strict,
+            wal: None,
+            oversize: Oversize::Ignore,
+            max_keys: None,
+            default_ttl: None,
+            sweep_interval: Duration::from_secs(1),
+            reserved: vec![],
+            tcp_port: None,
+            http_port: None,
+            replication_port: None,
+            primary: None,
+            replica_writes: ReplicaWrites::Reject,
+            replication_secret: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let defaults = Config::default();
+
+        let mode = match env_var::<String>("UNUSUALDB_MODE").as_deref() {
+            Some("extended") => Mode::Extended,
+            _ => Mode::Strict,
+        };
+
+        let wal = env_var::<String>("UNUSUALDB_WAL_PATH").map(|path| {
+            let mut wal = WalConfig::new(path);
+            if let Some(fsync) = env_var::<String>("UNUSUALDB_FSYNC").and_then(|s| Fsync::parse(&s))
+            {
+                wal.fsync = fsync;
+            }
+            if let Some(compact_after) = env_var("UNUSUALDB_COMPACT_AFTER") {
+                wal.compact_after = compact_after;
+            }
+            wal
+        });
+
+        let oversize = match env_var::<String>("UNUSUALDB_OVERSIZE").as_deref() {
+            Some("truncate") => Oversize::Truncate,
+            _ => Oversize::Ignore,
+        };
+
+        // `key=value` pairs separated by commas
+        let reserved = env_var::<String>("UNUSUALDB_RESERVED")
+            .map(|pairs| {
+                pairs
+                    .split(',')
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let replica_writes = match env_var::<String>("UNUSUALDB_REPLICA_WRITES").as_deref() {
+            Some("forward") => ReplicaWrites::Forward,
+            _ => ReplicaWrites::Reject,
+        };
+
+        Config {
+            mode,
+            reserved,
+            wal,
+            oversize,
+            max_keys: env_var("UNUSUALDB_MAX_KEYS"),
+            default_ttl: env_var("UNUSUALDB_DEFAULT_TTL").map(Duration::from_secs),
+            // a zero interval can't drive a timer
+            sweep_interval: env_var("UNUSUALDB_SWEEP_INTERVAL")
+                .map(Duration::from_millis)
+                .filter(|interval| !interval.is_zero())
+                .unwrap_or(defaults.sweep_interval),
+            tcp_port: env_var("UNUSUALDB_TCP_PORT"),
+            http_port: env_var("UNUSUALDB_HTTP_PORT"),
+            replication_port: env_var("UNUSUALDB_REPLICATION_PORT"),
+            primary: env_var("UNUSUALDB_PRIMARY"),
+            replica_writes,
+            replication_secret: env_var("UNUSUALDB_REPLICATION_SECRET"),
+        }
+    }
+}
+
+pub async fn start(port: &str, config: Config) -> Result<()> {
+    let address = format!("0.0.0.0:{}", port);
+    let socket = UdpSocket::bind(&address).await?;
+
+    println!("[{}] Server listening on {}", PREFIX, &address);
+
+    let (tcp_port, http_port) = (config.tcp_port.clone(), config.http_port.clone());
+    let replication_port = config.replication_port.clone();
+    let secret = config.replication_secret.clone();
+    let db = open(config).await?;
+
+    if let Some(tcp_port) = tcp_port {
+        let listener = TcpListener::bind(format!("0.0.0.0:{}", tcp_port)).await?;
+        println!("[{}] TCP listening on port {}", PREFIX, tcp_port);
+
+        let db = db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = tcp::serve(listener, db).await {
+                eprintln!("[{}] TCP listener failed: {}", PREFIX, e);
+            }
+        });
+    }
+
+    if let Some(http_port) = http_port {
+        let listener = TcpListener::bind(format!("0.0.0.0:{}", http_port)).await?;
+        println!("[{}] HTTP listening on port {}", PREFIX, http_port);
+
+        let db = db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(listener, db).await {
+                eprintln!("[{}] HTTP listener failed: {}", PREFIX, e);
+            }
+        });
+    }
+
+    if let Some(replication_port) = replication_port {
+        let listener = TcpListener::bind(format!("0.0.0.0:{}", replication_port)).await?;
+        println!(
+            "[{}] Replication listening on port {}",
+            PREFIX, replication_port
+        );
+
+        let db = db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = replication::serve(listener, db, secret).await {
+                eprintln!("[{}] Replication listener failed: {}", PREFIX, e);
+            }
+        });
+    }
+
+    serve(socket, db).await
+}
+
+/// Opens the database shared by every front end, syncing the log and
+/// sweeping expired keys in the background as configured and following the
+/// primary if there is one.
+async fn open(config: Config) -> Result<Shared> {
+    let (mode, sweep_interval) = (config.mode, config.sweep_interval);
+    let (primary, replica_writes) = (config.primary.clone(), config.replica_writes);
+    let secret = config.replication_secret.clone();
+
+    let mut database = Database::open(config).await?;
+    let sync_interval = database.wal.as_ref().and_then(Wal::sync_interval);
+    if primary.is_some() {
+        database.writes = match replica_writes {
+            ReplicaWrites::Reject => Writes::Rejected,
+            ReplicaWrites::Forward => Writes::Forwarded(None),
+        };
+    }
+    let db = Arc::new(Mutex::new(database));
+
+    if let Some(primary) = primary {
+        tokio::spawn(replication::follow(primary, db.clone(), secret));
+    }
+
+    if let Some(sync_interval) = sync_interval {
+        let db = db.clone();
+        tokio::spawn(async move {
+            let mut sync = tokio::time::interval(sync_interval);
+            loop {
+                sync.tick().await;
+                if let Some(wal) = &mut db.lock().await.wal {
+                    if let Err(e) = wal.sync().await {
+                        eprintln!("[{}] Failed to sync the log: {}", PREFIX, e);
+                    }
+                }
+            }
+        });
+    }
+
+    if mode == Mode::Extended {
+        let db = db.clone();
+        tokio::spawn(async move {
+            let mut sweep = tokio::time::interval(sweep_interval);
+            loop {
+                sweep.tick().await;
+                if let Err(e) = db.lock().await.sweep().await {
+                    eprintln!("[{}] Failed to sweep expired keys: {}", PREFIX, e);
+                }
+            }
+        });
+    }
+
+    Ok(db)
+}
+
+async fn serve(socket: UdpSocket, db: Shared) -> Result<()> {
+    // one byte more than allowed, so oversize requests can be told apart
+    let mut buffer = [0; MAX_DATAGRAM + 1];
+
+    loop {
+        let (bytes, origin) = socket.recv_from(&mut buffer).await?;
+
+        println!("[{}] Received message from {}", PREFIX, &origin);
+
+        // a failed request, like an insert the log couldn't take, is dropped
+        // rather than taking the listener down. The error is boxed and not
+        // `Send`, so it can't be held across the send below
+        let response = db
+            .lock()
+            .await
+            .handle(&buffer[0..bytes])
+            .await
+            .map_err(|e| e.to_string());
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Failed to handle request from {}: {}", origin, e);
+                continue;
+            }
+        };
+
+        if let Some(response) = response {
+            socket.send_to(&response, origin).await?;
+        }
+    }
+}
+
+/// Applies the oversize policy, returning `None` if the datagram should be
+/// dropped.
+fn fit(datagram: &[u8], oversize: Oversize) -> Option<&[u8]> {
+    match oversize {
+        _ if datagram.len() <= MAX_DATAGRAM => Some(datagram),
+        Oversize::Ignore => None,
+        Oversize::Truncate => Some(&datagram[..MAX_DATAGRAM]),
+    }
+}
+
+/// Splits off the `!<seconds> ` prefix extended mode allows before an
+/// insert to set its expiry, capped at `MAX_TTL`. Anything else is left as it
+/// is.
+fn split_ttl(request: &[u8]) -> (Option<Duration>, &[u8]) {
+    let split = request.strip_prefix(b"!").and_then(|rest| {
+        let space = rest.iter().position(|c| *c == b' ')?;
+        let seconds = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        let insert = &rest[space + 1..];
+        insert
+            .contains(&b'=')
+            .then_some((Duration::from_secs(seconds).min(MAX_TTL), insert))
+    });
+
+    match split {
+        Some((ttl, insert)) => (Some(ttl), insert),
+        None => (None, request),
+    }
+}
+
+/// The database as shared by the UDP, TCP and HTTP front ends.
+type Shared = Arc<Mutex<Database>>;
+
+/// The key-value entries, along with the log keeping them across restarts
+/// when one is configured. Keys and values are raw bytes, since the spec
+/// doesn't require them to be text.
+struct Database {
+    mode: Mode,
+    oversize: Oversize,
+    entries: Entries,
+    reserved: Registry,
+    wal: Option<Wal>,
+    default_ttl: Option<Duration>,
+    started: Instant,
+    requests: u64,
+    writes: Writes,
+    /// Every change made, for replicas to follow.
+    changes: broadcast::Sender<Change>,
+}
+
+/// What became of an insert.
+#[derive(Debug, PartialEq, Eq)]
+enum Inserted {
+    Stored,
+    Reserved,
+    Rejected,
+    Forwarded,
+    /// Meant for the primary, but it's unreachable or too far behind.
+    Unavailable,
+}
+
+impl Database {
+    async fn open(config: Config) -> Result<Database> {
+        let max_keys = match config.mode {
+            Mode::Extended => config.max_keys,
+            Mode::Strict => None,
+        };
+
+        let mut reserved = Registry::default();
+        reserved.insert_computed(b"version", |_| {
+            format!(
+                "luckywatcher's key-value store {}",
+                env!("CARGO_PKG_VERSION")
+            )
+            .into_bytes()
+        });
+        if config.mode == Mode::Extended {
+            reserved.insert_computed(b"uptime", |stats| {
+                stats.uptime.as_secs().to_string().into_bytes()
+            });
+            reserved.insert_computed(b"keys", |stats| stats.keys.to_string().into_bytes());
+            reserved.insert_computed(b"memory", |stats| stats.bytes.to_string().into_bytes());
+            reserved.insert_computed(b"requests", |stats| stats.requests.to_string().into_bytes());
+        }
+        for (key, value) in &config.reserved {
+            reserved.insert_static(key, value);
+        }
+
+        let mut db = Database {
+            mode: config.mode,
+            oversize: config.oversize,
+            entries: Entries::new(max_keys, config.mode == Mode::Extended),
+            reserved,
+            wal: None,
+            default_ttl: config.default_ttl,
+            started: Instant::now(),
+            requests: 0,
+            writes: Writes::Local,
+            changes: broadcast::channel(replication::BUFFER).0,
+        };
+
+        if let Some(wal) = config.wal {
+            let (wal, recovered) = Wal::open(wal).await?;
+            db.wal = Some(wal);
+
+            for (key, stored) in recovered {
+                let evicted = db.entries.insert(key, stored.value, stored.expires_at);
+                db.log_removals(evicted).await?;
+            }
+            db.sweep().await?;
+        }
+
+        Ok(db)
+    }
+
+    /// Answers one request in the UDP syntax, returning the response to send
+    /// if there is one.
+    async fn handle(&mut self, request: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.requests += 1;
+
+        let request = match fit(request, self.oversize) {
+            Some(request) => request,
+            None => {
+                println!("[{}] Ignoring oversize request", PREFIX);
+                return Ok(None);
+            }
+        };
+
+        let (ttl, request) = match self.mode {
+            Mode::Extended => split_ttl(request),
+            Mode::Strict => (None, request),
+        };
+
+        match parse_message(request) {
+            Message::Insert(key, value) => {
+                self.insert(key, value, ttl).await?;
+                Ok(None)
+            }
+            Message::Retrieve(key) => {
+                let mut response = key.to_vec();
+                response.push(b'=');
+                response.extend(self.get(key).unwrap_or_default());
+
+                let response = fit(&response, self.oversize).map(<[u8]>::to_vec);
+                if response.is_none() {
+                    println!("[{}] Not sending oversize response", PREFIX);
+                }
+                Ok(response)
+            }
+        }
+    }
+
+    /// Stores `value` under `key` unless the key is reserved or this is a
+    /// replica. In extended mode it expires after `ttl` or the default TTL,
+    /// where zero means never, capped at `MAX_TTL`.
+    async fn insert(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> Result<Inserted> {
+        if self.reserved.contains(key) {
+            return Ok(Inserted::Reserved);
+        }
+
+        match &self.writes {
+            Writes::Local => {}
+            Writes::Rejected => {
+                println!("[{}] Rejecting an insert to a replica", PREFIX);
+                return Ok(Inserted::Rejected);
+            }
+            Writes::Forwarded(primary) => {
+                let write = Write {
+                    key: key.to_vec(),
+                    value: value.to_vec(),
+                    ttl,
+                };
+                // fails once the connection's gone or its queue is full
+                let sent = primary
+                    .as_ref()
+                    .is_some_and(|primary| primary.try_send(write).is_ok());
+                if !sent {
+                    println!("[{}] Dropping an insert the primary can't take", PREFIX);
+                    return Ok(Inserted::Unavailable);
+                }
+                return Ok(Inserted::Forwarded);
+            }
+        }
+
+        let expires_at = match (self.mode, ttl.or(self.default_ttl)) {
+            (Mode::Extended, Some(ttl)) if !ttl.is_zero() => {
+                Some(now_millis().saturating_add(ttl.min(MAX_TTL).as_millis() as u64))
+            }
+            _ => None,
+        };
+
+        self.apply(Change::Insert {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            expires_at,
+        })
+        .await?;
+        Ok(Inserted::Stored)
+    }
+
+    /// Makes a change, made here or streamed from the primary. With a log,
+    /// an insert is appended before it's visible to retrieves.
+    async fn apply(&mut self, change: Change) -> Result<()> {
+        match change {
+            Change::Insert {
+                key,
+                value,
+                expires_at,
+            } => {
+                if let Some(wal) = &mut self.wal {
+                    wal.append_insert(&key, &value, expires_at).await?;
+                }
+                self.publish(|| Change::Insert {
+                    key: key.clone(),
+                    value: value.clone(),
+                    expires_at,
+                });
+                let evicted = self.entries.insert(key, value, expires_at);
+                self.log_removals(evicted).await
+            }
+            Change::Remove(key) => {
+                let removed = match self.entries.remove(&key) {
+                    true => vec![key],
+                    false => vec![],
+                };
+                self.log_removals(removed).await
+            }
+        }
+    }
+
+    /// Replaces the entries with a snapshot of inserts from the primary,
+    /// removing keys it doesn't have.
+    async fn resync(&mut self, snapshot: Vec<Change>) -> Result<()> {
+        let kept: HashSet<&[u8]> = snapshot
+            .iter()
+            .filter_map(|change| match change {
+                Change::Insert { key, .. } => Some(&key[..]),
+                Change::Remove(_) => None,
+            })
+            .collect();
+        let stale: Vec<Vec<u8>> = self
+            .entries
+            .iter()
+            .filter(|(key, _, _)| !kept.contains(key))
+            .map(|(key, _, _)| key.to_vec())
+            .collect();
+
+        for key in stale {
+            self.apply(Change::Remove(key)).await?;
+        }
+        for change in snapshot {
+            self.apply(change).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Subscribes to changes, along with a snapshot of the entries they
+    /// follow on from.
+    fn subscribe(&self) -> (broadcast::Receiver<Change>, Vec<Change>) {
+        let snapshot = self
+            .entries
+            .iter()
+            .map(|(key, value, expires_at)| Change::Insert {
+                key: key.to_vec(),
+                value: value.to_vec(),
+                expires_at,
+            })
+            .collect();
+
+        (self.changes.subscribe(), snapshot)
+    }
+
+    /// Passes a change on to replicas, only building it if there are any.
+    fn publish(&self, change: impl FnOnce() -> Change) {
+        if self.changes.receiver_count() > 0 {
+            // fails only if every replica has just gone
+            let _ = self.changes.send(change());
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        let stats = Stats {
+            uptime: self.started.elapsed(),
+            keys: self.entries.len(),
+            bytes: self.entries.bytes(),
+            requests: self.requests,
+        };
+
+        match self.reserved.get(key, &stats) {
+            Some(value) => Some(value),
+            None => self.entries.get(key, now_millis()).map(<[u8]>::to_vec),
+        }
+    }
+
+    /// Removes expired keys.
+    async fn sweep(&mut self) -> Result<()> {
+        let expired = self.entries.remove_expired(now_millis());
+        if !expired.is_empty() {
+            println!("[{}] Expired {} keys", PREFIX, expired.len());
+        }
+
+        self.log_removals(expired).await
+    }
+
+    /// Logs and publishes keys removed by eviction, expiry or a resync, then
+    /// compacts the log if it's due.
+    async fn log_removals(&mut self, removed: Vec<Vec<u8>>) -> Result<()> {
+        for key in removed {
+            if let Some(wal) = &mut self.wal {
+                wal.append_remove(&key).await?;
+            }
+            self.publish(|| Change::Remove(key));
+        }
+
+        if let Some(wal) = &mut self.wal {
+            if wal.needs_compaction() {
+                wal.compact(self.entries.iter()).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Message<'a> {
+    Insert(&'a [u8], &'a [u8]),
+    Retrieve(&'a [u8]),
+}
+
+/// Splits an insert at its first `=`, so values may contain more of them.
+fn parse_message(buffer: &[u8]) -> Message<'_> {
+    let maybe_index = buffer.iter().position(|c| c == &b'=');
+
+    if let Some(index) = maybe_index {
+        let key = &buffer[0..index];
+        let value = &buffer[index + 1..];
+
+        Message::Insert(key, value)
+    } else {
+        Message::Retrieve(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, path::Path, time::Duration};
+
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    async fn spawn_server(config: Config) -> (SocketAddr, tokio::task::JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let db = open(config).await.unwrap();
+        let server = tokio::spawn(async move { serve(socket, db).await.unwrap() });
+
+        (addr, server)
+    }
+
+    async fn retrieve(client: &UdpSocket, server: SocketAddr, key: &[u8]) -> Vec<u8> {
+        client.send_to(key, server).await.unwrap();
+
+        let mut buffer = [0; 2000];
+        let bytes = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        buffer[..bytes].to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_data_survives_restart() {
+        let dir = std::env::temp_dir().join(format!("unusual-db-{}", uuid::Uuid::new_v4()));
+        let config = Config {
+            wal: Some(WalConfig {
+                fsync: Fsync::Always,
+                compact_after: 4,
+                ..WalConfig::new(&dir)
+            }),
+            ..Config::default()
+        };
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let (addr, server) = spawn_server(config.clone()).await;
+        for i in 0..10 {
+            let message = format!("key{}=value{}", i % 6, i);
+            client.send_to(message.as_bytes(), addr).await.unwrap();
+        }
+        client.send_to(b"version=hacked", addr).await.unwrap();
+        // replies come after every earlier insert was handled
+        assert_eq!(retrieve(&client, addr, b"key0").await, b"key0=value6");
+
+        // kill the server without letting it clean up
+        server.abort();
+        assert!(server.await.unwrap_err().is_cancelled());
+        assert!(Path::new(&dir).join("snapshot").exists());
+
+        let (addr, _server) = spawn_server(config).await;
+        for (key, expected) in [
+            ("key0", "key0=value6"),
+            ("key3", "key3=value9"),
+            ("key5", "key5=value5"),
+            ("missing", "missing="),
+            ("version", "version=luckywatcher's key-value store 0.1.0"),
+        ] {
+            assert_eq!(
+                retrieve(&client, addr, key.as_bytes()).await,
+                expected.as_bytes()
+            );
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_message() {
+        assert_eq!(
+            parse_message(b"foo=bar=baz"),
+            Message::Insert(b"foo", b"bar=baz")
+        );
+        assert_eq!(parse_message(b"foo="), Message::Insert(b"foo", b""));
+        assert_eq!(parse_message(b"=foo"), Message::Insert(b"", b"foo"));
+        assert_eq!(parse_message(b"==="), Message::Insert(b"", b"=="));
+        assert_eq!(parse_message(b""), Message::Retrieve(b""));
+        assert_eq!(parse_message(b"\xff\x00"), Message::Retrieve(b"\xff\x00"));
+    }
+
+    #[tokio::test]
+    async fn test_binary_keys_and_oversize_datagrams() {
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (addr, _server) = spawn_server(Config::default()).await;
+
+        client.send_to(b"\xff\xfe=\x80=\x00", addr).await.unwrap();
+        client.send_to(b"=empty", addr).await.unwrap();
+        assert_eq!(
+            retrieve(&client, addr, b"\xff\xfe").await,
+            b"\xff\xfe=\x80=\x00"
+        );
+        assert_eq!(retrieve(&client, addr, b"").await, b"=empty");
+
+        // a 1000 byte request is ignored, then the same key with a value
+        // that just fits is stored
+        let mut insert = b"big=".to_vec();
+        insert.resize(1000, b'x');
+        client.send_to(&insert, addr).await.unwrap();
+        assert_eq!(retrieve(&client, addr, b"big").await, b"big=");
+        client.send_to(&insert[..999], addr).await.unwrap();
+        assert_eq!(retrieve(&client, addr, b"big").await, &insert[..999]);
+
+        let truncating = Config {
+            oversize: Oversize::Truncate,
+            ..Config::default()
+        };
+        let (addr, _server) = spawn_server(truncating).await;
+        insert.resize(1500, b'y');
+        client.send_to(&insert, addr).await.unwrap();
+        assert_eq!(retrieve(&client, addr, b"big").await, &insert[..999]);
+    }
+
+    #[test]
+    fn test_split_ttl() {
+        assert_eq!(
+            split_ttl(b"!30 a=b"),
+            (Some(Duration::from_secs(30)), &b"a=b"[..])
+        );
+        assert_eq!(
+            split_ttl(b"!0 a=!1 b"),
+            (Some(Duration::ZERO), &b"a=!1 b"[..])
+        );
+        // not an insert, or not a number, so it's an ordinary key
+        assert_eq!(split_ttl(b"!30 a"), (None, &b"!30 a"[..]));
+        assert_eq!(split_ttl(b"!x a=b"), (None, &b"!x a=b"[..]));
+        assert_eq!(split_ttl(b"a=b"), (None, &b"a=b"[..]));
+        assert_eq!(
+            split_ttl(b"!18446744073709551615 k=v"),
+            (Some(MAX_TTL), &b"k=v"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_huge_ttls() {
+        let config = Config {
+            mode: Mode::Extended,
+            default_ttl: Some(Duration::from_secs(u64::MAX)),
+            ..Config::default()
+        };
+        let shared = open(config).await.unwrap();
+        let http_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_addr = http_listener.local_addr().unwrap();
+        let http_db = shared.clone();
+        tokio::spawn(async move { http::serve(http_listener, http_db).await.unwrap() });
+
+        let huge = format!("/keys/d?ttl={}", MAX_TTL.as_secs() + 1);
+        assert!(http_request(http_addr, "PUT", &huge, b"4")
+            .await
+            .starts_with("400 "));
+        let longest = format!("/keys/d?ttl={}", MAX_TTL.as_secs());
+        assert_eq!(http_request(http_addr, "PUT", &longest, b"4").await, "204 ");
+
+        let mut db = shared.lock().await;
+        db.handle(b"!18446744073709551 a=1").await.unwrap();
+        db.handle(b"b=2").await.unwrap();
+        let forwarded = Some(Duration::MAX);
+        assert_eq!(
+            db.insert(b"c", b"3", forwarded).await.unwrap(),
+            Inserted::Stored
+        );
+
+        let latest = now_millis() + MAX_TTL.as_millis() as u64;
+        for key in [&b"a"[..], b"b", b"c", b"d"] {
+            let (_, _, expires_at) = db.entries.iter().find(|(k, _, _)| *k == key).unwrap();
+            assert!(expires_at.is_some_and(|expires_at| expires_at <= latest));
+        }
+        assert_eq!(db.handle(b"a").await.unwrap().unwrap(), b"a=1");
+    }
+
+    #[tokio::test]
+    async fn test_extended_mode() {
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            mode: Mode::Extended,
+            max_keys: Some(3),
+            sweep_interval: Duration::from_millis(50),
+            reserved: vec![(b"motd".to_vec(), b"hello".to_vec())],
+            ..Config::default()
+        };
+        let (addr, _server) = spawn_server(config).await;
+
+        // datagrams sent to the server, which `requests` should count
+        let mut sent = 0;
+
+        for insert in [&b"!1 short=lived"[..], b"a=1", b"b=2", b"keys=100"] {
+            client.send_to(insert, addr).await.unwrap();
+            sent += 1;
+        }
+        assert_eq!(retrieve(&client, addr, b"short").await, b"short=lived");
+        assert_eq!(retrieve(&client, addr, b"keys").await, b"keys=3");
+        sent += 2;
+
+        // `a` is evicted as the least recently used
+        client.send_to(b"c=3", addr).await.unwrap();
+        assert_eq!(retrieve(&client, addr, b"a").await, b"a=");
+        assert_eq!(retrieve(&client, addr, b"b").await, b"b=2");
+        sent += 3;
+
+        // the sweeper drops `short` without it being retrieved again
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        assert_eq!(retrieve(&client, addr, b"keys").await, b"keys=2");
+        assert_eq!(retrieve(&client, addr, b"short").await, b"short=");
+        let memory = retrieve(&client, addr, b"memory").await;
+        assert!(memory.starts_with(b"memory=") && memory.len() > 7);
+        let uptime = retrieve(&client, addr, b"uptime").await;
+        let uptime: u64 = std::str::from_utf8(uptime.strip_prefix(b"uptime=").unwrap())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(uptime >= 1);
+        sent += 4;
+
+        client.send_to(b"motd=hacked", addr).await.unwrap();
+        assert_eq!(retrieve(&client, addr, b"motd").await, b"motd=hello");
+        // counting the retrieve of `requests` itself
+        sent += 3;
+        assert_eq!(
+            retrieve(&client, addr, b"requests").await,
+            format!("requests={}", sent).as_bytes()
+        );
+
+        // strict mode has no stats keys and no expiry syntax
+        let (addr, _server) = spawn_server(Config::default()).await;
+        client.send_to(b"keys=100", addr).await.unwrap();
+        client.send_to(b"!1 short=lived", addr).await.unwrap();
+        assert_eq!(retrieve(&client, addr, b"keys").await, b"keys=100");
+        assert_eq!(
+            retrieve(&client, addr, b"!1 short").await,
+            b"!1 short=lived"
+        );
+    }
+
+    async fn http_request(addr: SocketAddr, method: &str, path: &str, body: &[u8]) -> String {
+        let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let head = format!(
+            "{} {} HTTP/1.1\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            method,
+            path,
+            body.len()
+        );
+        socket.write_all(head.as_bytes()).await.unwrap();
+        socket.write_all(body).await.unwrap();
+
+        let mut response = vec![];
+        socket.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response).into_owned();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        format!("{} {}", &head[9..12], body.trim_end())
+    }
+
+    #[tokio::test]
+    async fn test_front_ends_share_the_store() {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let udp_addr = udp.local_addr().unwrap();
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp_addr = tcp_listener.local_addr().unwrap();
+        let http_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_addr = http_listener.local_addr().unwrap();
+
+        let db = open(Config::default()).await.unwrap();
+        let (tcp_db, http_db) = (db.clone(), db.clone());
+        tokio::spawn(async move { tcp::serve(tcp_listener, tcp_db).await.unwrap() });
+        tokio::spawn(async move { http::serve(http_listener, http_db).await.unwrap() });
+        tokio::spawn(async move { serve(udp, db).await.unwrap() });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"udp=1", udp_addr).await.unwrap();
+        assert_eq!(retrieve(&client, udp_addr, b"udp").await, b"udp=1");
+
+        let tcp = tokio::net::TcpStream::connect(tcp_addr).await.unwrap();
+        let (reader, mut writer) = tcp.into_split();
+        let mut reader = tokio::io::BufReader::new(reader);
+        writer
+            .write_all(b"tcp=2=3\r\nudp\nversion=x\nversion\n")
+            .await
+            .unwrap();
+        for expected in ["udp=1\n", "version=luckywatcher's key-value store 0.1.0\n"] {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            assert_eq!(line, expected);
+        }
+        assert_eq!(retrieve(&client, udp_addr, b"tcp").await, b"tcp=2=3");
+
+        assert_eq!(
+            http_request(http_addr, "PUT", "/keys/http", b"4").await,
+            "204 "
+        );
+        assert_eq!(retrieve(&client, udp_addr, b"http").await, b"http=4");
+        assert_eq!(
+            http_request(http_addr, "PUT", "/keys/%FF%20a", b"5").await,
+            "204 "
+        );
+        assert_eq!(retrieve(&client, udp_addr, b"\xff a").await, b"\xff a=5");
+        assert_eq!(
+            http_request(http_addr, "GET", "/keys/%ff%20a", b"").await,
+            "200 5"
+        );
+        assert_eq!(
+            http_request(http_addr, "GET", "/keys/tcp", b"").await,
+            "200 2=3"
+        );
+        assert_eq!(
+            http_request(http_addr, "GET", "/keys/nope", b"").await,
+            "404 no such key"
+        );
+        assert_eq!(
+            http_request(http_addr, "PUT", "/keys/version", b"x").await,
+            "403 key is reserved"
+        );
+        assert_eq!(
+            http_request(http_addr, "PUT", "/keys/big", &[b'x'; 996]).await,
+            "413 key and value must be under 1000 bytes"
+        );
+        for path in ["/keys/a%3Db", "/keys/a%0Ab"] {
+            assert_eq!(
+                http_request(http_addr, "PUT", path, b"x").await,
+                "400 key can't contain = or a newline"
+            );
+        }
+        assert_eq!(retrieve(&client, udp_addr, b"a").await, b"a=");
+        assert_eq!(
+            http_request(http_addr, "DELETE", "/keys/tcp", b"").await,
+            "405 method not allowed"
+        );
+        assert_eq!(
+            http_request(http_addr, "GET", "/other", b"").await,
+            "404 not found"
+        );
+    }
+
+    /// Waits for `key` to be replicated to `db` with `expected` as its value.
+    async fn replicated(db: &Shared, key: &[u8], expected: Option<&[u8]>) {
+        for _ in 0..100 {
+            if db.lock().await.get(key).as_deref() == expected {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("{:?} was never replicated", String::from_utf8_lossy(key));
+    }
+
+    #[tokio::test]
+    async fn test_replication() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let primary = open(Config::default()).await.unwrap();
+        primary
+            .lock()
+            .await
+            .insert(b"early", b"1", None)
+            .await
+            .unwrap();
+        let feed_db = primary.clone();
+        let feed =
+            tokio::spawn(async move { replication::serve(listener, feed_db, None).await.unwrap() });
+
+        let replica = |replica_writes| Config {
+            primary: Some(addr.to_string()),
+            replica_writes,
+            ..Config::default()
+        };
+        let forwarding = open(replica(ReplicaWrites::Forward)).await.unwrap();
+        let rejecting = open(replica(ReplicaWrites::Reject)).await.unwrap();
+
+        // replicas start from a snapshot, then follow each insert
+        replicated(&forwarding, b"early", Some(b"1")).await;
+        replicated(&rejecting, b"early", Some(b"1")).await;
+        primary
+            .lock()
+            .await
+            .insert(b"early", b"2", None)
+            .await
+            .unwrap();
+        replicated(&rejecting, b"early", Some(b"2")).await;
+
+        // a forwarded insert comes back to both replicas through the primary
+        forwarding
+            .lock()
+            .await
+            .handle(b"forwarded=3")
+            .await
+            .unwrap();
+        replicated(&rejecting, b"forwarded", Some(b"3")).await;
+        replicated(&forwarding, b"forwarded", Some(b"3")).await;
+
+        let inserted = rejecting
+            .lock()
+            .await
+            .insert(b"rejected", b"4", None)
+            .await
+            .unwrap();
+        assert_eq!(inserted, Inserted::Rejected);
+        primary
+            .lock()
+            .await
+            .insert(b"marker", b"5", None)
+            .await
+            .unwrap();
+        replicated(&rejecting, b"marker", Some(b"5")).await;
+        assert_eq!(primary.lock().await.get(b"rejected"), None);
+        assert_eq!(rejecting.lock().await.get(b"rejected"), None);
+
+        // replicas keep serving reads without the primary, and resync from a
+        // new snapshot once it's back
+        feed.abort();
+        assert!(feed.await.unwrap_err().is_cancelled());
+        primary
+            .lock()
+            .await
+            .insert(b"missed", b"6", None)
+            .await
+            .unwrap();
+        assert_eq!(forwarding.lock().await.get(b"missed"), None);
+        assert_eq!(forwarding.lock().await.get(b"early"), Some(b"2".to_vec()));
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move { replication::serve(listener, primary, None).await.unwrap() });
+        replicated(&forwarding, b"missed", Some(b"6")).await;
+        replicated(&rejecting, b"missed", Some(b"6")).await;
+
+        // keys the primary no longer has are dropped on a resync
+        let mut rejecting = rejecting.lock().await;
+        rejecting
+            .resync(vec![Change::Insert {
+                key: b"only".to_vec(),
+                value: b"7".to_vec(),
+                expires_at: None,
+            }])
+            .await
+            .unwrap();
+        assert_eq!(rejecting.get(b"only"), Some(b"7".to_vec()));
+        assert_eq!(
+            (rejecting.get(b"early"), rejecting.entries.len()),
+            (None, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forwarding_without_the_primary() {
+        let mut db = Database::open(Config::default()).await.unwrap();
+
+        db.writes = Writes::Forwarded(None);
+        let inserted = db.insert(b"a", b"1", None).await.unwrap();
+        assert_eq!(inserted, Inserted::Unavailable);
+
+        // a full queue turns inserts away until the connection catches up
+        let (forward, mut forwarded) = tokio::sync::mpsc::channel(1);
+        db.writes = Writes::Forwarded(Some(forward));
+        assert_eq!(
+            db.insert(b"a", b"1", None).await.unwrap(),
+            Inserted::Forwarded
+        );
+        assert_eq!(
+            db.insert(b"b", b"2", None).await.unwrap(),
+            Inserted::Unavailable
+        );
+        assert_eq!(forwarded.recv().await.unwrap().key, b"a");
+        assert_eq!(
+            db.insert(b"c", b"3", None).await.unwrap(),
+            Inserted::Forwarded
+        );
+
+        drop(forwarded);
+        assert_eq!(
+            db.insert(b"d", b"4", None).await.unwrap(),
+            Inserted::Unavailable
+        );
+        assert_eq!(db.get(b"a"), None);
+    }
+
+    /// Finds a port nothing is listening on, for servers started by port.
+    async fn free_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    /// Sends an HTTP request until it's answered with `expected`.
+    async fn http_until(addr: SocketAddr, method: &str, path: &str, body: &[u8], expected: &str) {
+        let mut response = String::new();
+        for _ in 0..100 {
+            // until the server is listening
+            if tokio::net::TcpStream::connect(addr).await.is_ok() {
+                response = http_request(addr, method, path, body).await;
+                if response == expected {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!(
+            "{} {} answered {:?}, not {:?}",
+            method, path, response, expected
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replication_secret() {
+        let replication_port = free_port().await.to_string();
+        let primary_http: SocketAddr = format!("127.0.0.1:{}", free_port().await).parse().unwrap();
+        let primary = Config {
+            http_port: Some(primary_http.port().to_string()),
+            replication_port: Some(replication_port.clone()),
+            replication_secret: Some("hunter2".to_string()),
+            ..Config::default()
+        };
+        let udp_port = free_port().await.to_string();
+        tokio::spawn(async move { start(&udp_port, primary).await.map_err(|e| e.to_string()) });
+
+        let replica = |secret: &str, http_port: u16| Config {
+            http_port: Some(http_port.to_string()),
+            primary: Some(format!("127.0.0.1:{}", replication_port)),
+            replica_writes: ReplicaWrites::Forward,
+            replication_secret: Some(secret.to_string()),
+            ..Config::default()
+        };
+        let mut replicas = vec![];
+        for secret in ["hunter2", "wrong"] {
+            let http: SocketAddr = format!("127.0.0.1:{}", free_port().await).parse().unwrap();
+            let config = replica(secret, http.port());
+            let udp_port = free_port().await.to_string();
+            tokio::spawn(async move { start(&udp_port, config).await.map_err(|e| e.to_string()) });
+            replicas.push(http);
+        }
+        let (trusted, untrusted) = (replicas[0], replicas[1]);
+
+        // an insert through the replica reaches the primary and comes back
+        http_until(
+            trusted,
+            "PUT",
+            "/keys/a",
+            b"1",
+            "202 forwarded to the primary",
+        )
+        .await;
+        http_until(primary_http, "GET", "/keys/a", b"", "200 1").await;
+        http_until(trusted, "GET", "/keys/a", b"", "200 1").await;
+
+        // a replica with the wrong secret is never sent the store nor heard
+        http_until(untrusted, "GET", "/keys/a", b"", "404 no such key").await;
+        http_until(
+            untrusted,
+            "PUT",
+            "/keys/b",
+            b"2",
+            "503 primary is unavailable",
+        )
+        .await;
+        assert_eq!(
+            http_request(primary_http, "GET", "/keys/b", b"").await,
+            "404 no such key"
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::{broadcast::error::RecvError, mpsc},
+    task::JoinSet,
+};
+
+use crate::util::Result;
+
+use super::{wal, Shared, PREFIX};
+
+/// Changes queued for a replica before it's dropped for falling behind. It
+/// resyncs from a snapshot when it reconnects.
+pub const BUFFER: usize = 4096;
+
+/// Inserts queued for the primary before more are turned away.
+pub const FORWARD_BUFFER: usize = 1024;
+
+/// Wait between attempts to reach the primary.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long a replica has to send its secret after connecting.
+const SECRET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a replica does with inserts from its own clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplicaWrites {
+    /// Drop them, as a read-only copy.
+    #[default]
+    Reject,
+    /// Pass them on to the primary. They show up on the replica once the
+    /// primary streams them back.
+    Forward,
+}
+
+/// A change to the entries, encoded for replicas as it is for the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Insert {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+    },
+    Remove(Vec<u8>),
+}
+
+/// An insert forwarded from a replica to its primary, which works out the
+/// expiry.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Write {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub ttl: Option<Duration>,
+}
+
+/// Where inserts from clients go.
+pub enum Writes {
+    Local,
+    Rejected,
+    /// Queued for the connection to the primary, or `None` while there's no
+    /// connection.
+    Forwarded(Option<mpsc::Sender<Write>>),
+}
+
+/// What either end sends. A replica first sends `S` and the length prefixed
+/// secret, empty if it has none. The primary then sends `B`, a snapshot of
+/// inserts and `E`, then each change as it's made. A replica sends `W`, the
+/// length prefixed key and value and, if it has one, the TTL.
+#[derive(Debug, PartialEq, Eq)]
+enum Frame {
+    Secret(Vec<u8>),
+    Begin,
+    End,
+    Change(Change),
+    Write(Write),
+}
+
+impl Change {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Change::Insert {
+                key,
+                value,
+                expires_at,
+            } => wal::encode_insert(key, value, *expires_at),
+            Change::Remove(key) => wal::encode_remove(key),
+        }
+    }
+}
+
+impl Write {
+    fn encode(&self) -> Vec<u8> {
+        let mut frame = vec![b'W'];
+        for field in [&self.key, &self.value] {
+            frame.extend((field.len() as u32).to_be_bytes());
+            frame.extend(field);
+        }
+        match self.ttl {
+            Some(ttl) => {
+                frame.push(1);
+                frame.extend(ttl.as_secs().to_be_bytes());
+            }
+            None => frame.push(0),
+        }
+        frame
+    }
+}
+
+/// Streams the store to every replica that connects with the same `secret`
+/// and applies the inserts they forward. Replicas are disconnected when this
+/// stops, and resync once it's back.
+pub async fn serve(listener: TcpListener, db: Shared, secret: Option<String>) -> Result<()> {
+    let mut replicas = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (socket, addr) = accepted?;
+                replicas.spawn(feed_replica(socket, addr, db.clone(), secret.clone()));
+            }
+            // reap replicas that have gone
+            Some(_) = replicas.join_next(), if !replicas.is_empty() => {}
+        }
+    }
+}
+
+async fn feed_replica(socket: TcpStream, addr: SocketAddr, db: Shared, secret: Option<String>) {
+    println!("[{}] Replica connected from {}", PREFIX, addr);
+
+    let (reader, writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    // the error is boxed and not `Send`, so it can't be held across the
+    // streaming below
+    let checked = check_secret(&mut reader, secret.as_deref())
+        .await
+        .map_err(|e| e.to_string());
+    let result = match checked {
+        Ok(()) => tokio::select! {
+            result = stream_changes(writer, &db) => result,
+            result = receive_writes(reader, &db) => result,
+        },
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(()) => println!("[{}] Replica {} disconnected", PREFIX, addr),
+        Err(e) => eprintln!("[{}] Dropping replica {}: {}", PREFIX, addr, e),
+    }
+}
+
+/// Waits for the replica's secret, which must match the primary's. Without a
+/// secret on either end it's empty.
+async fn check_secret(reader: &mut BufReader<OwnedReadHalf>, secret: Option<&str>) -> Result<()> {
+    let frame = match tokio::time::timeout(SECRET_TIMEOUT, read_frame(reader)).await {
+        Ok(frame) => frame?,
+        Err(_) => return Err("no secret sent in time".into()),
+    };
+
+    match frame {
+        Some(Frame::Secret(sent)) if sent == secret.unwrap_or_default().as_bytes() => Ok(()),
+        Some(Frame::Secret(_)) => Err("wrong secret".into()),
+        Some(_) => Err("expected a secret first".into()),
+        None => Err("closed before sending a secret".into()),
+    }
+}
+
+async fn stream_changes(writer: OwnedWriteHalf, db: &Shared) -> Result<()> {
+    let mut writer = BufWriter::new(writer);
+    let (mut changes, snapshot) = db.lock().await.subscribe();
+
+    writer.write_u8(b'B').await?;
+    for change in &snapshot {
+        writer.write_all(&change.encode()).await?;
+    }
+    writer.write_u8(b'E').await?;
+    writer.flush().await?;
+
+    println!("[{}] Sent a snapshot of {} keys", PREFIX, snapshot.len());
+
+    loop {
+        let change = match changes.recv().await {
+            Ok(change) => change,
+            Err(RecvError::Lagged(missed)) => {
+                return Err(format!("fell {} changes behind", missed).into())
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+
+        writer.write_all(&change.encode()).await?;
+        writer.flush().await?;
+    }
+}
+
+async fn receive_writes(mut reader: BufReader<OwnedReadHalf>, db: &Shared) -> Result<()> {
+    loop {
+        let frame = read_frame(&mut reader).await?;
+        match frame {
+            Some(Frame::Write(write)) => {
+                db.lock()
+                    .await
+                    .insert(&write.key, &write.value, write.ttl)
+                    .await?;
+            }
+            Some(frame) => return Err(format!("unexpected {:?} from a replica", frame).into()),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Replicates the primary at `addr`, reconnecting and resyncing whenever the
+/// connection drops. While connected, forwarded inserts are passed on to it.
+pub async fn follow(addr: String, db: Shared, secret: Option<String>) {
+    loop {
+        match replicate(&addr, &db, secret.as_deref()).await {
+            Ok(()) => println!("[{}] Primary {} closed the connection", PREFIX, addr),
+            Err(e) => eprintln!("[{}] Lost the primary {}: {}", PREFIX, addr, e),
+        }
+
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+async fn replicate(addr: &str, db: &Shared, secret: Option<&str>) -> Result<()> {
+    let socket = TcpStream::connect(addr).await?;
+    println!("[{}] Replicating from {}", PREFIX, addr);
+
+    let (reader, mut writer) = socket.into_split();
+    writer
+        .write_all(&encode_secret(secret.unwrap_or_default()))
+        .await?;
+
+    // the primary closes the connection on a wrong secret, and otherwise
+    // starts sending a snapshot
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf().await?.is_empty() {
+        return Err("turned away, the secrets may not match".into());
+    }
+
+    // the queue goes with the connection, so inserts are turned away rather
+    // than held for a later one once it drops
+    let (forward, mut forwarded) = mpsc::channel(FORWARD_BUFFER);
+    if let Writes::Forwarded(primary) = &mut db.lock().await.writes {
+        *primary = Some(forward);
+    }
+
+    tokio::select! {
+        result = apply_changes(reader, db) => result,
+        result = forward_writes(writer, &mut forwarded) => result,
+    }
+}
+
+async fn apply_changes(mut reader: BufReader<OwnedReadHalf>, db: &Shared) -> Result<()> {
+    // a snapshot is applied in one go, so reads never see part of one
+    let mut snapshot = None;
+
+    loop {
+        let frame = read_frame(&mut reader).await?;
+        match frame {
+            Some(Frame::Begin) => snapshot = Some(vec![]),
+            Some(Frame::End) => {
+                let snapshot = snapshot.take().ok_or("snapshot ended before it began")?;
+                let keys = snapshot.len();
+                db.lock().await.resync(snapshot).await?;
+                println!("[{}] Resynced {} keys from the primary", PREFIX, keys);
+            }
+            Some(Frame::Change(change)) => match &mut snapshot {
+                Some(snapshot) => snapshot.push(change),
+                None => db.lock().await.apply(change).await?,
+            },
+            Some(frame) => return Err(format!("unexpected {:?} from the primary", frame).into()),
+            None => return Ok(()),
+        }
+    }
+}
+
+async fn forward_writes(
+    mut writer: OwnedWriteHalf,
+    forwarded: &mut mpsc::Receiver<Write>,
+) -> Result<()> {
+    while let Some(write) = forwarded.recv().await {
+        writer.write_all(&write.encode()).await?;
+    }
+
+    // writes are rejected, so there's never anything to forward
+    std::future::pending().await
+}
+
+fn encode_secret(secret: &str) -> Vec<u8> {
+    let mut frame = vec![b'S'];
+    frame.extend((secret.len() as u32).to_be_bytes());
+    frame.extend(secret.as_bytes());
+    frame
+}
+
+/// Reads the next frame, or `None` once the other end has closed the
+/// connection.
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> Result<Option<Frame>> {
+    let kind = match reader.read_u8().await {
+        Ok(kind) => kind,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let frame = match kind {
+        b'S' => Frame::Secret(read_field(reader).await?),
+        b'B' => Frame::Begin,
+        b'E' => Frame::End,
+        b'I' => {
+            let key = read_field(reader).await?;
+            let value = read_field(reader).await?;
+            let expires_at = reader.read_u64().await?;
+            Frame::Change(Change::Insert {
+                key,
+                value,
+                expires_at: (expires_at != 0).then_some(expires_at),
+            })
+        }
+        b'R' => Frame::Change(Change::Remove(read_field(reader).await?)),
+        b'W' => {
+            let key = read_field(reader).await?;
+            let value = read_field(reader).await?;
+            let ttl = match reader.read_u8().await? {
+                0 => None,
+                _ => Some(Duration::from_secs(reader.read_u64().await?)),
+            };
+            Frame::Write(Write { key, value, ttl })
+        }
+        kind => return Err(format!("unknown frame type {:#04x}", kind).into()),
+    };
+
+    Ok(Some(frame))
+}
+
+async fn read_field(reader: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
+    let length = reader.read_u32().await?;
+    if length > wal::MAX_FIELD {
+        return Err(format!("field of {} bytes is too long", length).into());
+    }
+
+    let mut field = vec![0; length as usize];
+    reader.read_exact(&mut field).await?;
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frames_round_trip() {
+        let frames = [
+            Frame::Secret(b"hunter2".to_vec()),
+            Frame::Begin,
+            Frame::Change(Change::Insert {
+                key: b"a".to_vec(),
+                value: b"\xff=".to_vec(),
+                expires_at: Some(1000),
+            }),
+            Frame::End,
+            Frame::Change(Change::Insert {
+                key: vec![],
+                value: vec![],
+                expires_at: None,
+            }),
+            Frame::Change(Change::Remove(b"a".to_vec())),
+            Frame::Write(Write {
+                key: b"b".to_vec(),
+                value: b"2".to_vec(),
+                ttl: Some(Duration::from_secs(60)),
+            }),
+            Frame::Write(Write {
+                key: b"c".to_vec(),
+                value: b"3".to_vec(),
+                ttl: None,
+            }),
+        ];
+
+        let mut bytes = vec![];
+        for frame in &frames {
+            match frame {
+                Frame::Secret(secret) => {
+                    bytes.extend(encode_secret(std::str::from_utf8(secret).unwrap()))
+                }
+                Frame::Begin => bytes.push(b'B'),
+                Frame::End => bytes.push(b'E'),
+                Frame::Change(change) => bytes.extend(change.encode()),
+                Frame::Write(write) => bytes.extend(write.encode()),
+            }
+        }
+
+        let mut reader = &bytes[..];
+        for frame in frames {
+            assert_eq!(read_frame(&mut reader).await.unwrap(), Some(frame));
+        }
+        assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+
+        assert!(read_frame(&mut &b"X"[..]).await.is_err());
+        assert!(read_frame(&mut &b"R\xff\xff\xff\xff"[..]).await.is_err());
+    }
+}
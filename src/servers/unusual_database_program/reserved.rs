@@ -0,0 +1,74 @@
+use std::{collections::HashMap, time::Duration};
+
+/// Server state computed keys are evaluated from.
+pub struct Stats {
+    pub uptime: Duration,
+    pub keys: usize,
+    pub bytes: usize,
+    pub requests: u64,
+}
+
+pub type Compute = fn(&Stats) -> Vec<u8>;
+
+enum Value {
+    Static(Vec<u8>),
+    Computed(Compute),
+}
+
+/// Read-only keys clients can't overwrite, either fixed or evaluated each
+/// time they're read.
+#[derive(Default)]
+pub struct Registry {
+    keys: HashMap<Vec<u8>, Value>,
+}
+
+impl Registry {
+    /// Reserves `key`, replacing any earlier definition of it.
+    pub fn insert_static(&mut self, key: &[u8], value: &[u8]) {
+        self.keys
+            .insert(key.to_vec(), Value::Static(value.to_vec()));
+    }
+
+    pub fn insert_computed(&mut self, key: &[u8], compute: Compute) {
+        self.keys.insert(key.to_vec(), Value::Computed(compute));
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.keys.contains_key(key)
+    }
+
+    pub fn get(&self, key: &[u8], stats: &Stats) -> Option<Vec<u8>> {
+        match self.keys.get(key)? {
+            Value::Static(value) => Some(value.clone()),
+            Value::Computed(compute) => Some(compute(stats)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::default();
+        registry.insert_computed(b"keys", |stats| stats.keys.to_string().into_bytes());
+        registry.insert_static(b"motd", b"hello");
+
+        let mut stats = Stats {
+            uptime: Duration::ZERO,
+            keys: 3,
+            bytes: 0,
+            requests: 0,
+        };
+        assert_eq!(registry.get(b"keys", &stats), Some(b"3".to_vec()));
+        stats.keys = 4;
+        assert_eq!(registry.get(b"keys", &stats), Some(b"4".to_vec()));
+        assert_eq!(registry.get(b"motd", &stats), Some(b"hello".to_vec()));
+        assert_eq!(registry.get(b"other", &stats), None);
+
+        registry.insert_static(b"keys", b"fixed");
+        assert_eq!(registry.get(b"keys", &stats), Some(b"fixed".to_vec()));
+        assert!(registry.contains(b"keys") && !registry.contains(b"other"));
+    }
+}
@@ -0,0 +1,83 @@
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::util::Result;
+
+use super::{Shared, PREFIX};
+
+/// Longest line read before the connection is dropped. Requests are held to
+/// the datagram limit after that, like UDP ones.
+const MAX_LINE: u64 = 64 * 1024;
+
+/// Serves the UDP protocol with one request per line, for clients that
+/// can't send UDP. Retrieves are answered with a `key=value` line. A trailing
+/// `\r` is dropped, and keys and values can't contain newlines.
+pub async fn serve(listener: TcpListener, db: Shared) -> Result<()> {
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        let db = db.clone();
+
+        tokio::spawn(async move {
+            handle_connection(socket, addr, db).await;
+        });
+    }
+}
+
+async fn handle_connection(socket: TcpStream, addr: SocketAddr, db: Shared) {
+    println!("[{}] TCP connection established from {}", PREFIX, addr);
+
+    let (read_half, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    loop {
+        let mut line = vec![];
+        match (&mut reader)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut line)
+            .await
+        {
+            Ok(0) => return,
+            Ok(_) if line.last() != Some(&b'\n') && line.len() as u64 == MAX_LINE => {
+                eprintln!("[{}] Line from {} is too long", PREFIX, addr);
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Failed to read from socket: {}", e);
+                return;
+            }
+        }
+
+        let request = line
+            .strip_suffix(b"\n")
+            .map(|request| request.strip_suffix(b"\r").unwrap_or(request))
+            .unwrap_or(&line);
+
+        // the error is boxed and not `Send`, so it can't be held across the
+        // write below
+        let response = db
+            .lock()
+            .await
+            .handle(request)
+            .await
+            .map_err(|e| e.to_string());
+        let mut response = match response {
+            Ok(Some(response)) => response,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Failed to handle request: {}", e);
+                return;
+            }
+        };
+
+        response.push(b'\n');
+        if let Err(e) = writer.write_all(&response).await {
+            eprintln!("Failed to send message to socket: {}", e);
+            return;
+        }
+    }
+}
@@ -0,0 +1,550 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::util::Result;
+
+use super::PREFIX;
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const LOG_FILE: &str = "log";
+
+/// Starts both files, followed by a format version byte.
+const MAGIC: &[u8] = b"UDBWAL";
+const VERSION: u8 = 1;
+const HEADER: &[u8] = b"UDBWAL\x01";
+
+/// Longest key or value in a record, whether read from disk or from a
+/// replication peer.
+pub const MAX_FIELD: u32 = 1 << 20;
+
+/// When appended inserts are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fsync {
+    /// After every insert, before it's acknowledged in memory.
+    Always,
+    /// From a timer once per interval whenever something was appended since
+    /// the last sync, so about one interval of inserts can be lost on a
+    /// crash.
+    Interval(Duration),
+    /// Whenever the OS gets to it.
+    Never,
+}
+
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+    pub dir: PathBuf,
+    pub fsync: Fsync,
+    /// Inserts appended to the log before it's folded into the snapshot.
+    pub compact_after: usize,
+}
+
+/// Append-only log of changes next to a snapshot of everything before them.
+/// Both files start with `UDBWAL` and a version byte, then hold the same
+/// records, and the log is replayed over the snapshot on startup. An insert
+/// is `I`, the length prefixed key and value and the expiry time in
+/// milliseconds (0 for none). A removal is `R` and the length prefixed key.
+/// Each record is followed by its CRC-32.
+pub struct Wal {
+    config: WalConfig,
+    log: File,
+    appended: usize,
+    /// Whether anything was appended since the log was last synced.
+    unsynced: bool,
+}
+
+/// A value recovered from disk.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Stored {
+    pub value: Vec<u8>,
+    pub expires_at: Option<u64>,
+}
+
+impl Fsync {
+    /// Parses `always`, `never` or a number of milliseconds. A zero interval
+    /// can't drive a timer, so it's refused and the default applies.
+    pub fn parse(s: &str) -> Option<Fsync> {
+        match s {
+            "always" => Some(Fsync::Always),
+            "never" => Some(Fsync::Never),
+            millis => millis
+                .parse()
+                .ok()
+                .filter(|millis| *millis > 0)
+                .map(|millis| Fsync::Interval(Duration::from_millis(millis))),
+        }
+    }
+}
+
+impl WalConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> WalConfig {
+        WalConfig {
+            dir: dir.into(),
+            fsync: Fsync::Interval(Duration::from_secs(1)),
+            compact_after: 10_000,
+        }
+    }
+}
+
+impl Wal {
+    /// Opens the log in `config.dir`, returning it along with the entries
+    /// recovered from the snapshot and log.
+    pub async fn open(config: WalConfig) -> Result<(Wal, HashMap<Vec<u8>, Stored>)> {
+        fs::create_dir_all(&config.dir).await?;
+
+        // the snapshot is renamed into place whole, so unlike the log it
+        // can't end in a partial record
+        let mut entries = HashMap::new();
+        read_records(&config.dir.join(SNAPSHOT_FILE), &mut entries, false).await?;
+        let (complete, appended) =
+            read_records(&config.dir.join(LOG_FILE), &mut entries, true).await?;
+
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(config.dir.join(LOG_FILE))
+            .await?;
+
+        if complete == 0 {
+            log.set_len(0).await?;
+            log.write_all(HEADER).await?;
+            log.sync_all().await?;
+        } else if log.metadata().await?.len() > complete {
+            // drop a record left half written by a crash
+            println!(
+                "[{}] Dropping a partial record at the end of the log",
+                PREFIX
+            );
+            log.set_len(complete).await?;
+        }
+
+        println!(
+            "[{}] Recovered {} keys from {}",
+            PREFIX,
+            entries.len(),
+            config.dir.display()
+        );
+
+        let wal = Wal {
+            config,
+            log,
+            appended,
+            unsynced: false,
+        };
+
+        Ok((wal, entries))
+    }
+
+    pub async fn append_insert(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<()> {
+        self.append(&checksummed(encode_insert(key, value, expires_at)))
+            .await
+    }
+
+    pub async fn append_remove(&mut self, key: &[u8]) -> Result<()> {
+        self.append(&checksummed(encode_remove(key))).await
+    }
+
+    async fn append(&mut self, record: &[u8]) -> Result<()> {
+        self.log.write_all(record).await?;
+        self.log.flush().await?;
+        self.appended += 1;
+        self.unsynced = true;
+
+        if self.config.fsync == Fsync::Always {
+            self.sync().await?;
+        }
+
+        Ok(())
+    }
+
+    /// How often `sync` should be called, if the log is synced on a timer.
+    pub fn sync_interval(&self) -> Option<Duration> {
+        match self.config.fsync {
+            Fsync::Interval(interval) => Some(interval),
+            _ => None,
+        }
+    }
+
+    /// Syncs anything appended since the last sync to disk.
+    pub async fn sync(&mut self) -> Result<()> {
+        if self.unsynced {
+            self.log.sync_data().await?;
+            self.unsynced = false;
+        }
+
+        Ok(())
+    }
+
+    pub fn needs_compaction(&self) -> bool {
+        self.appended >= self.config.compact_after
+    }
+
+    /// Replaces the snapshot with `entries` and empties the log. The new
+    /// snapshot is renamed into place, so a crash leaves either the old
+    /// snapshot and full log or the new one, and replaying the log over the
+    /// new snapshot is harmless.
+    pub async fn compact<'a>(
+        &mut self,
+        entries: impl Iterator<Item = (&'a [u8], &'a [u8], Option<u64>)>,
+    ) -> Result<()> {
+        let snapshot = self.config.dir.join(SNAPSHOT_FILE);
+        let temporary = snapshot.with_extension("tmp");
+
+        let mut records = HEADER.to_vec();
+        let mut count = 0;
+        for (key, value, expires_at) in entries {
+            records.extend(checksummed(encode_insert(key, value, expires_at)));
+            count += 1;
+        }
+
+        let mut file = File::create(&temporary).await?;
+        file.write_all(&records).await?;
+        file.sync_all().await?;
+        fs::rename(&temporary, &snapshot).await?;
+
+        self.log.set_len(HEADER.len() as u64).await?;
+        self.log.sync_all().await?;
+        self.appended = 0;
+        self.unsynced = false;
+
+        println!("[{}] Compacted {} keys into a snapshot", PREFIX, count);
+
+        Ok(())
+    }
+}
+
+pub fn encode_insert(key: &[u8], value: &[u8], expires_at: Option<u64>) -> Vec<u8> {
+    let mut record = vec![b'I'];
+    for field in [key, value] {
+        record.extend((field.len() as u32).to_be_bytes());
+        record.extend(field);
+    }
+    record.extend(expires_at.unwrap_or(0).to_be_bytes());
+    record
+}
+
+pub fn encode_remove(key: &[u8]) -> Vec<u8> {
+    let mut record = vec![b'R'];
+    record.extend((key.len() as u32).to_be_bytes());
+    record.extend(key);
+    record
+}
+
+/// Appends the CRC-32 a record is stored with.
+fn checksummed(mut record: Vec<u8>) -> Vec<u8> {
+    let checksum = crc32(&record);
+    record.extend(checksum.to_be_bytes());
+    record
+}
+
+/// Replays the records in `path` into `entries`, returning the length of
+/// the header and complete records and how many records there were. A
+/// missing, empty or half written header counts as no records at all, and
+/// a record cut short at the end is only tolerated if `partial_tail` is set.
+/// Anything else that doesn't parse is an error rather than being dropped.
+async fn read_records(
+    path: &Path,
+    entries: &mut HashMap<Vec<u8>, Stored>,
+    partial_tail: bool,
+) -> Result<(u64, usize)> {
+    let bytes = match fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e.into()),
+    };
+
+    if bytes.len() < HEADER.len() && HEADER.starts_with(&bytes) {
+        return Ok((0, 0));
+    }
+    if !bytes.starts_with(MAGIC) {
+        return Err(format!(
+            "{} has no log header, it may be from an older version",
+            path.display()
+        )
+        .into());
+    }
+    if bytes[MAGIC.len()] != VERSION {
+        return Err(format!(
+            "{} is log format version {}, expected {}",
+            path.display(),
+            bytes[MAGIC.len()],
+            VERSION
+        )
+        .into());
+    }
+
+    let mut rest = &bytes[HEADER.len()..];
+    let mut count = 0;
+    loop {
+        let offset = bytes.len() - rest.len();
+        match read_record(rest, entries) {
+            _ if rest.is_empty() => break,
+            Ok(Some(after)) => {
+                rest = after;
+                count += 1;
+            }
+            Ok(None) if partial_tail => break,
+            Ok(None) => {
+                let message = format!("{} ends in a partial record", path.display());
+                return Err(message.into());
+            }
+            Err(e) => {
+                let message = format!("{} is corrupt at byte {}: {}", path.display(), offset, e);
+                return Err(message.into());
+            }
+        }
+    }
+
+    Ok(((bytes.len() - rest.len()) as u64, count))
+}
+
+/// Applies the record at the start of `bytes`, returning what follows it,
+/// or `None` if it's incomplete.
+fn read_record<'a>(
+    bytes: &'a [u8],
+    entries: &mut HashMap<Vec<u8>, Stored>,
+) -> std::result::Result<Option<&'a [u8]>, String> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    let kind = match reader.take(1) {
+        Some(kind) => kind[0],
+        None => return Ok(None),
+    };
+    let key = match reader.field()? {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    let stored = match kind {
+        b'I' => {
+            let value = match reader.field()? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            let expires_at = match reader.take(8) {
+                Some(expires_at) => u64::from_be_bytes(expires_at.try_into().unwrap()),
+                None => return Ok(None),
+            };
+            Some(Stored {
+                value: value.to_vec(),
+                expires_at: (expires_at != 0).then_some(expires_at),
+            })
+        }
+        b'R' => None,
+        kind => return Err(format!("unknown record type {:#04x}", kind)),
+    };
+
+    let record = &bytes[..reader.position];
+    let checksum = match reader.take(4) {
+        Some(checksum) => u32::from_be_bytes(checksum.try_into().unwrap()),
+        None => return Ok(None),
+    };
+    if checksum != crc32(record) {
+        return Err("checksum mismatch".to_string());
+    }
+
+    match stored {
+        Some(stored) => entries.insert(key.to_vec(), stored),
+        None => entries.remove(key),
+    };
+
+    Ok(Some(&bytes[reader.position..]))
+}
+
+/// Walks a record's fields, with `None` once the bytes run out.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let taken = self.bytes.get(self.position..self.position + length)?;
+        self.position += length;
+        Some(taken)
+    }
+
+    /// A length prefixed field. A length past `MAX_FIELD` can't have been
+    /// written, so it's corruption rather than a record cut short.
+    fn field(&mut self) -> std::result::Result<Option<&'a [u8]>, String> {
+        let length = match self.take(4) {
+            Some(length) => u32::from_be_bytes(length.try_into().unwrap()),
+            None => return Ok(None),
+        };
+        if length > MAX_FIELD {
+            return Err(format!("field of {} bytes is too long", length));
+        }
+
+        Ok(self.take(length as usize))
+    }
+}
+
+/// CRC-32 as used by zip and PNG.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_recovers_snapshot_and_log() {
+        let dir = std::env::temp_dir().join(format!("unusual-db-{}", uuid::Uuid::new_v4()));
+        let config = WalConfig {
+            compact_after: 3,
+            ..WalConfig::new(&dir)
+        };
+
+        let (mut wal, mut entries) = Wal::open(config.clone()).await.unwrap();
+        let inserts: [(&[u8], &[u8], Option<u64>); 5] = [
+            (b"a", b"1", None),
+            (b"b", b"2", Some(1000)),
+            (b"a", b"3", None),
+            (b"", b"\xff\xfe", Some(2000)),
+            (b"=", b"x=y", None),
+        ];
+        for (key, value, expires_at) in inserts {
+            wal.append_insert(key, value, expires_at).await.unwrap();
+            let value = value.to_vec();
+            entries.insert(key.to_vec(), Stored { value, expires_at });
+            if wal.needs_compaction() {
+                let snapshot = entries
+                    .iter()
+                    .map(|(key, stored)| (&key[..], &stored.value[..], stored.expires_at));
+                wal.compact(snapshot).await.unwrap();
+            }
+        }
+        wal.append_remove(b"b").await.unwrap();
+        entries.remove(&b"b"[..]);
+        drop(wal);
+
+        // the first three inserts were compacted and the rest are in the log,
+        // followed by a record cut short by a crash
+        let log = dir.join(LOG_FILE);
+        let mut file = OpenOptions::new().append(true).open(&log).await.unwrap();
+        file.write_all(&encode_insert(b"d", b"4", None)[..6])
+            .await
+            .unwrap();
+        drop(file);
+
+        let (wal, recovered) = Wal::open(config).await.unwrap();
+        assert_eq!(recovered, entries);
+        assert_eq!(wal.appended, 3);
+        assert_eq!(
+            fs::metadata(&log).await.unwrap().len(),
+            (HEADER.len()
+                + checksummed(encode_insert(b"", b"\xff\xfe", Some(2000))).len()
+                + checksummed(encode_insert(b"=", b"x=y", None)).len()
+                + checksummed(encode_remove(b"b")).len()) as u64
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_corrupt_and_foreign_logs() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let dir = std::env::temp_dir().join(format!("unusual-db-{}", uuid::Uuid::new_v4()));
+        let config = WalConfig::new(&dir);
+        let log = dir.join(LOG_FILE);
+
+        let (mut wal, _) = Wal::open(config.clone()).await.unwrap();
+        wal.append_insert(b"a", b"1", None).await.unwrap();
+        wal.append_insert(b"b", b"2", None).await.unwrap();
+        drop(wal);
+        let valid = fs::read(&log).await.unwrap();
+
+        // a flipped byte in the first record
+        let mut corrupt = valid.clone();
+        corrupt[HEADER.len() + 5] ^= 1;
+        fs::write(&log, &corrupt).await.unwrap();
+        let e = Wal::open(config.clone()).await.err().unwrap();
+        assert!(e.to_string().contains("checksum mismatch"), "{}", e);
+        assert_eq!(fs::read(&log).await.unwrap(), corrupt);
+
+        // a length that can't have been written isn't taken for a crash
+        let mut corrupt = valid.clone();
+        corrupt[HEADER.len() + 1] = 0xff;
+        fs::write(&log, &corrupt).await.unwrap();
+        let e = Wal::open(config.clone()).await.err().unwrap();
+        assert!(e.to_string().contains("too long"), "{}", e);
+
+        // records in the format from before the header
+        fs::write(&log, b"\0\0\0\x01a\0\0\0\x011").await.unwrap();
+        let e = Wal::open(config.clone()).await.err().unwrap();
+        assert!(e.to_string().contains("no log header"), "{}", e);
+
+        let mut other_version = valid.clone();
+        other_version[MAGIC.len()] = 2;
+        fs::write(&log, &other_version).await.unwrap();
+        assert!(Wal::open(config.clone()).await.is_err());
+
+        // a header cut short by a crash is rewritten
+        fs::write(&log, &HEADER[..3]).await.unwrap();
+        let (_, recovered) = Wal::open(config.clone()).await.unwrap();
+        assert!(recovered.is_empty());
+        assert_eq!(fs::read(&log).await.unwrap(), HEADER);
+
+        // the snapshot is renamed into place whole, so a partial record there
+        // is corruption too
+        fs::write(dir.join(SNAPSHOT_FILE), &valid[..valid.len() - 2])
+            .await
+            .unwrap();
+        let e = Wal::open(config).await.err().unwrap();
+        assert!(e.to_string().contains("partial record"), "{}", e);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_fsync() {
+        assert_eq!(Fsync::parse("always"), Some(Fsync::Always));
+        assert_eq!(Fsync::parse("never"), Some(Fsync::Never));
+        assert_eq!(
+            Fsync::parse("250"),
+            Some(Fsync::Interval(Duration::from_millis(250)))
+        );
+        assert_eq!(Fsync::parse("0"), None);
+        assert_eq!(Fsync::parse("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_interval_sync() {
+        let dir = std::env::temp_dir().join(format!("unusual-db-{}", uuid::Uuid::new_v4()));
+        let config = WalConfig {
+            fsync: Fsync::Interval(Duration::from_secs(60)),
+            ..WalConfig::new(&dir)
+        };
+
+        let (mut wal, _) = Wal::open(config).await.unwrap();
+        assert_eq!(wal.sync_interval(), Some(Duration::from_secs(60)));
+
+        // appends wait for the timer however long it's been
+        wal.append_insert(b"a", b"1", None).await.unwrap();
+        assert!(wal.unsynced);
+        wal.sync().await.unwrap();
+        assert!(!wal.unsynced);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1 +1,22 @@
+use std::str::FromStr;
+
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Reads and parses an environment variable, returning `None` if it is unset
+/// or fails to parse.
+pub fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+/// Small deterministic xorshift64 generator for benchmarks and tests. The
+/// seed must not be zero.
+pub struct XorShift(pub u64);
+
+impl XorShift {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}